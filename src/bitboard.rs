@@ -0,0 +1,91 @@
+//! Bitboards, one bit per square with a1 as bit 0, b1 as bit 1 up to h8 as bit 63,
+//! and the attack tables move generation is built on
+
+/// Squares a knight on a square jumps to
+pub static KNIGHT_ATTACKS: [u64; 64] = jump_table(&[(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)]);
+/// Squares around a square
+pub static KING_ATTACKS: [u64; 64] = jump_table(&[(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)]);
+/// Squares a pawn on a square takes on, white pawns first
+pub static PAWN_ATTACKS: [[u64; 64]; 2] = [jump_table(&[(-1, 1), (1, 1)]), jump_table(&[(-1, -1), (1, -1)])];
+
+/// Rays go towards higher squares in the first four directions, towards lower ones in the rest
+const DIRECTIONS: [(i64, i64); 8] = [(0, 1), (1, 1), (1, 0), (-1, 1), (0, -1), (-1, -1), (-1, 0), (1, -1)];
+const ROOK_DIRECTIONS: [usize; 4] = [0, 2, 4, 6];
+const BISHOP_DIRECTIONS: [usize; 4] = [1, 3, 5, 7];
+/// Squares from a square to the edge of the board, not including it, by direction
+static RAYS: [[u64; 64]; 8] = ray_table();
+
+const fn jump_table(jumps: &[(i64, i64)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let x = (square % 8) as i64;
+        let y = (square / 8) as i64;
+        let mut i = 0;
+        while i < jumps.len() {
+            let (to_x, to_y) = (x + jumps[i].0, y + jumps[i].1);
+            if to_x >= 0 && to_x < 8 && to_y >= 0 && to_y < 8 {
+                table[square] |= 1 << (to_y * 8 + to_x);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+const fn ray_table() -> [[u64; 64]; 8] {
+    let mut table = [[0; 64]; 8];
+    let mut dir = 0;
+    while dir < 8 {
+        let mut square = 0;
+        while square < 64 {
+            let mut x = (square % 8) as i64 + DIRECTIONS[dir].0;
+            let mut y = (square / 8) as i64 + DIRECTIONS[dir].1;
+            while x >= 0 && x < 8 && y >= 0 && y < 8 {
+                table[dir][square] |= 1 << (y * 8 + x);
+                x += DIRECTIONS[dir].0;
+                y += DIRECTIONS[dir].1;
+            }
+            square += 1;
+        }
+        dir += 1;
+    }
+    table
+}
+
+/// Squares seen along a ray, up to and including the first occupied one
+fn ray_attacks(dir: usize, square: usize, occupied: u64) -> u64 {
+    let ray = RAYS[dir][square];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let first = if dir < 4 { blockers.trailing_zeros() } else { 63 - blockers.leading_zeros() };
+    ray ^ RAYS[dir][first as usize]
+}
+
+pub fn rook_attacks(square: usize, occupied: u64) -> u64 {
+    ROOK_DIRECTIONS.iter().fold(0, |attacks, dir| attacks | ray_attacks(*dir, square, occupied))
+}
+
+pub fn bishop_attacks(square: usize, occupied: u64) -> u64 {
+    BISHOP_DIRECTIONS.iter().fold(0, |attacks, dir| attacks | ray_attacks(*dir, square, occupied))
+}
+
+/// Iterates over the squares of a bitboard, lowest first
+pub struct Squares(pub u64);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            None
+        } else {
+            let square = self.0.trailing_zeros() as usize;
+            self.0 &= self.0 - 1;
+            Some(square)
+        }
+    }
+}
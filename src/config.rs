@@ -0,0 +1,74 @@
+//! Settings read from environment variables when the bot starts
+
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+
+#[derive(Clone)]
+pub struct Config {
+    /// Directory finished games are saved to as PGN, `TAKE_PGN_DIR`, `games` by default
+    pub pgn_dir: PathBuf,
+    /// Whether saved games get our evaluation as comments, `TAKE_PGN_EVALS`
+    pub pgn_evals: bool,
+    /// Plies the search looks ahead at most, `TAKE_DEPTH`
+    pub search_depth: u32,
+    /// Milliseconds a move may take at most, `TAKE_MAX_MOVE_TIME`
+    pub max_move_time: u64,
+    /// Megabytes of the transposition table of each game, `TAKE_HASH`
+    pub hash_mb: usize,
+    /// Search threads shared by all games being played, `TAKE_THREADS`, one per core by default
+    pub threads: usize,
+    /// Whether to search on the opponent's time, `TAKE_PONDER`
+    pub ponder: bool,
+    pub pruning: Pruning,
+}
+
+/// Ways the search skips or shortens lines, each can be turned off to measure what it's worth
+#[derive(Copy, Clone)]
+pub struct Pruning {
+    /// `TAKE_NULL_MOVE`
+    pub null_move: bool,
+    /// `TAKE_LMR`
+    pub late_move_reductions: bool,
+    /// `TAKE_FUTILITY`
+    pub futility: bool,
+    /// `TAKE_REVERSE_FUTILITY`
+    pub reverse_futility: bool,
+    /// `TAKE_CHECK_EXTENSIONS`
+    pub check_extensions: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        Config {
+            pgn_dir: PathBuf::from(env::var("TAKE_PGN_DIR").unwrap_or_else(|_| "games".to_string())),
+            pgn_evals: flag("TAKE_PGN_EVALS", false),
+            search_depth: number("TAKE_DEPTH", 64),
+            max_move_time: number("TAKE_MAX_MOVE_TIME", 20_000),
+            hash_mb: number("TAKE_HASH", 16),
+            threads: number("TAKE_THREADS", thread::available_parallelism().map_or(1, |n| n.get())),
+            ponder: flag("TAKE_PONDER", true),
+            pruning: Pruning {
+                null_move: flag("TAKE_NULL_MOVE", true),
+                late_move_reductions: flag("TAKE_LMR", true),
+                futility: flag("TAKE_FUTILITY", true),
+                reverse_futility: flag("TAKE_REVERSE_FUTILITY", true),
+                check_extensions: flag("TAKE_CHECK_EXTENSIONS", true),
+            },
+        }
+    }
+}
+
+fn number<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// `1`, `true`, `yes` or `on` turn a switch on, `0`, `false`, `no` or `off` turn it off
+fn flag(name: &str, default: bool) -> bool {
+    match env::var(name).map(|v| v.to_lowercase()) {
+        Ok(v) if ["1", "true", "yes", "on"].contains(&&*v) => true,
+        Ok(v) if ["0", "false", "no", "off"].contains(&&*v) => false,
+        _ => default
+    }
+}
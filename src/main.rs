@@ -1,5 +1,19 @@
 extern crate serde_json;
 
+mod bitboard;
+mod config;
+mod eval;
+mod movepick;
+mod pawns;
+mod perft;
+mod pgn;
+mod san;
+mod time;
+mod search;
+mod see;
+mod tt;
+mod zobrist;
+
 use serde::{Deserialize, Serialize};
 
 use ansi_term::Color;
@@ -7,14 +21,27 @@ use std::fmt::{Display, Formatter, Error};
 use reqwest;
 use curl::easy::{Easy, List};
 use std::io::Read;
+use std::ops::Deref;
 use serde::export::fmt::Debug;
-use std::cmp::max;
-use rand::{thread_rng, Rng};
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use PlayerColor::*;
+use config::Config;
+use pawns::PawnTables;
+use pgn::Pgn;
+use search::{Ponder, Search};
+use tt::TranspositionTable;
+use time::TimeManager;
+use bitboard::{Squares, KNIGHT_ATTACKS, KING_ATTACKS, PAWN_ATTACKS, bishop_attacks, rook_attacks};
 
 const TOKEN: &str = env!("taketoken");
 
+/// Games whose stream is open, `Config::threads` is split between them
+static ACTIVE_GAMES: AtomicUsize = AtomicUsize::new(0);
+
 const A1: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
 const A2: [&str; 8] = ["1", "2", "3", "4", "5", "6", "7", "8"];
 
@@ -32,6 +59,18 @@ impl PlayerColor {
             Unknown => Color::RGB(42, 42, 42)
         }
     }
+    /// 0 for white, 1 for black, to index per color tables
+    fn index(&self) -> usize {
+        if *self == White { 0 } else { 1 }
+    }
+    /// Pieces a pawn of this color may promote to
+    fn promotions(&self) -> [Piece; 4] {
+        if *self == White {
+            [Piece::WhiteQueen, Piece::WhiteRook, Piece::WhiteBishop, Piece::WhiteKnight]
+        } else {
+            [Piece::BlackQueen, Piece::BlackRook, Piece::BlackBishop, Piece::BlackKnight]
+        }
+    }
 }
 
 const ENDPOINT_BASE: &str = "https://lichess.org";
@@ -56,18 +95,25 @@ const ENDPOINT_DECLINE: &str = "/api/challenge/{}/decline";
 #[allow(unused)]
 const ENDPOINT_UPGRADE: &str = "/api/bot/account/upgrade";
 const ENDPOINT_RESIGN: &str = "/api/bot/game/{}/resign";
+const ENDPOINT_DRAW: &str = "/api/bot/game/{}/draw/{}";
 
 const ENDPOINT_TABLEBASE: &str = "http://tablebase.lichess.ovh/standard?fen=";
 
 fn main() {
-    let bot = Bot::new();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|s| s.as_str()) {
+        Some("perft") => return perft::run(&args[2..]),
+        Some("pgn") => return pgn::run(&args[2..]),
+        _ => {}
+    }
+    let bot = Bot::new(Config::from_env());
     bot.setup_events();
 }
 
-fn get_tablebase_move(table: &Table, color: PlayerColor) -> Option<Move> {
-    let res = reqwest::blocking::get(&format!("{}{}", ENDPOINT_TABLEBASE, table.fen(color))).unwrap().text().unwrap();
+fn get_tablebase_move(table: &Table) -> Option<Move> {
+    let res = reqwest::blocking::get(&format!("{}{}", ENDPOINT_TABLEBASE, table.fen())).unwrap().text().unwrap();
     if let Ok(res) = serde_json::from_str::<TablebaseResponse>(&res) {
-        Some(Move::from_str(&*res.moves[0].uci))
+        res.moves.first().and_then(|m| table.parse_san(&m.san))
     } else {
         None
     }
@@ -84,7 +130,9 @@ struct Challenge {
     variant: String,
 }
 
-struct Bot;
+struct Bot {
+    config: Config,
+}
 
 #[allow(unused)]
 #[derive(Deserialize)]
@@ -117,8 +165,8 @@ struct TablebaseResponseMove {
 }
 
 impl Bot {
-    fn new() -> Bot {
-        Bot
+    fn new(config: Config) -> Bot {
+        Bot { config }
     }
 
     fn setup_events(mut self) {
@@ -152,7 +200,7 @@ impl Bot {
         } else if let Ok(game) = serde_json::from_str::<GameStartEvent>(&s) {
             let game = game.game;
             println!("Started game {}!", game.id);
-            let game = Game::new(game.id);
+            let game = Game::new(game.id, self.config.clone());
             game.setup_events();
         } else {
             println!("Unknown event: {}", s)
@@ -296,17 +344,42 @@ impl ChatMessage {
 struct Game {
     moves: Vec<Move>,
     table: Table,
+    /// Position the game started from
+    initial: Table,
     id: String,
     my_color: PlayerColor,
+    config: Config,
+    /// Players, time control and the rest of what lichess tells when the stream opens
+    full: Option<GameFullEvent>,
+    /// Clock in milliseconds of the player who made the move, by ply
+    clocks: HashMap<usize, i64>,
+    /// Evaluation in centipawns from white's side after each of our moves, by ply
+    evals: HashMap<usize, i32>,
+    /// Kept for the whole game, what was searched for one move helps with the next
+    tt: Arc<TranspositionTable>,
+    /// Kept for the whole game like `tt`, pawn structures change little from move to move
+    pawns: PawnTables,
+    time: TimeManager,
+    /// Searching the position after the move we expect, while the opponent thinks
+    ponder: Option<Ponder>,
 }
 
 impl Game {
-    fn new(id: String) -> Game {
+    fn new(id: String, config: Config) -> Game {
         Game {
             moves: Vec::new(),
             table: Table::default(),
+            initial: Table::default(),
             id,
             my_color: Unknown,
+            full: None,
+            clocks: HashMap::new(),
+            evals: HashMap::new(),
+            tt: Arc::new(TranspositionTable::new(config.hash_mb)),
+            pawns: PawnTables::new(),
+            time: TimeManager::new(Duration::from_millis(config.max_move_time)),
+            ponder: None,
+            config,
         }
     }
     #[allow(unused)]
@@ -314,6 +387,7 @@ impl Game {
         post(ENDPOINT_CHAT, vec!(self.id.clone()), Option::Some(ChatMessage::new(message.to_string())));
     }
     fn setup_events(mut self) {
+        ACTIVE_GAMES.fetch_add(1, Ordering::Relaxed);
         std::thread::Builder::new().name(self.id.clone()).spawn(move || {
             let url = format!("{}{}", ENDPOINT_BASE, ENDPOINT_STREAM.replace("{}", &self.id));
             let mut easy = Easy::new();
@@ -331,7 +405,9 @@ impl Game {
                 }
                 Ok(size)
             }).unwrap();
-            easy.perform().unwrap();
+            let result = easy.perform();
+            ACTIVE_GAMES.fetch_sub(1, Ordering::Relaxed);
+            result.unwrap();
         }).unwrap();
     }
 
@@ -346,37 +422,18 @@ impl Game {
             };
             println!("Game {} started! We are playing {}", game_full.id, color);
             self.my_color = if color == "White" { White } else { Black };
-            if self.my_color == White {
-                self.make_move()
-            }
-        } else if let Ok(game_state) = serde_json::from_str::<GameStateEvent>(&s) {
-            self.moves = game_state.moves.split(" ").map(|s| Move::from_str(s)).collect();
-            self.table.sync(&self.moves);
-            match game_state.status.as_str() {
-                "started" => {
-                    if self.moves.len() % 2 == 0 && self.my_color == White || self.moves.len() % 2 == 1 && self.my_color == Black {
-                        self.make_move();
-                    }
-                }
-                "mate" => {
-                    self.end();
-                }
-                "draw" => {
-                    self.end()
-                }
-                "resign" => {
-                    self.end()
-                }
-                "stalemate" => {
-                    self.end()
-                }
-                "outoftime" => {
-                    self.end()
-                }
-                _ => {
-                    println!("Unknown game status: {}", game_state.status);
+            if game_full.initial_fen != "startpos" {
+                match Table::from_fen(&game_full.initial_fen) {
+                    Ok(table) => self.initial = table,
+                    Err(err) => println!("Can't read initial position {}: {}", game_full.initial_fen, err)
                 }
             }
+            self.table = self.initial.clone();
+            let state = game_full.state.clone();
+            self.full = Some(game_full);
+            self.on_game_state(state);
+        } else if let Ok(game_state) = serde_json::from_str::<GameStateEvent>(&s) {
+            self.on_game_state(game_state);
         } else if let Ok(chat_line) = serde_json::from_str::<ChatLineEvent>(&s) {
             println!("Chat: {}: {}", chat_line.username, chat_line.text);
         } else {
@@ -384,54 +441,220 @@ impl Game {
         }
     }
 
-    fn end(&mut self) {
-        // TODO
+    fn on_game_state(&mut self, game_state: GameStateEvent) {
+        self.moves = game_state.moves.split_whitespace().map(|s| Move::from_str(s)).collect();
+        self.table.sync(&self.initial, &self.moves);
+        if !self.moves.is_empty() {
+            // The clock of whoever moved last, the other one is still running
+            let time = if self.table.turn == White { game_state.btime } else { game_state.wtime };
+            self.clocks.insert(self.moves.len() - 1, time);
+        }
+        match game_state.status.as_str() {
+            "started" => {
+                if self.table.turn == self.my_color {
+                    self.make_move(&game_state);
+                }
+            }
+            "created" => {}
+            // Every other status means the game is over, however it ended
+            _ => {
+                self.end(&game_state)
+            }
+        }
+    }
+
+    fn end(&mut self, game_state: &GameStateEvent) {
+        self.ponder = None;
+        let path = self.config.pgn_dir.join(format!("{}.pgn", self.id));
+        let pgn = self.pgn(game_state).to_string();
+        match fs::create_dir_all(&self.config.pgn_dir).and_then(|_| fs::write(&path, pgn)) {
+            Ok(()) => println!("Saved game {} to {}", self.id, path.display()),
+            Err(err) => println!("Can't save game {} to {}: {}", self.id, path.display(), err)
+        }
+    }
+
+    fn pgn(&self, game_state: &GameStateEvent) -> Pgn {
+        let result = match (game_state.winner.as_deref(), game_state.status.as_str()) {
+            (Some("white"), _) => "1-0",
+            (Some("black"), _) => "0-1",
+            // Without a winner, running out of time or leaving against bare kings draws
+            (_, "draw") | (_, "stalemate") | (_, "outoftime") | (_, "timeout") | (_, "variantEnd") => "1/2-1/2",
+            _ => "*"
+        }.to_string();
+        let termination = match game_state.status.as_str() {
+            "mate" | "resign" | "stalemate" | "draw" | "variantEnd" => "normal",
+            "outoftime" => "time forfeit",
+            "timeout" | "aborted" | "noStart" => "abandoned",
+            "cheat" => "rules infraction",
+            _ => "unterminated"
+        };
+
+        let mut tags = Vec::new();
+        let mut tag = |name: &str, value: String| tags.push((name.to_string(), value));
+        match &self.full {
+            Some(full) => {
+                tag("Event", format!("{} {} game", if full.rated { "Rated" } else { "Casual" }, full.perf.name));
+                tag("Site", format!("{}/{}", ENDPOINT_BASE, self.id));
+                tag("Date", pgn::date(full.created_at));
+                tag("Round", "-".to_string());
+                tag("White", full.white.name.clone());
+                tag("Black", full.black.name.clone());
+                tag("Result", result.clone());
+                tag("GameId", self.id.clone());
+                tag("WhiteElo", full.white.rating.to_string());
+                tag("BlackElo", full.black.rating.to_string());
+                tag("TimeControl", match &full.clock {
+                    Some(clock) => format!("{}+{}", clock.initial / 1000, clock.increment / 1000),
+                    None => "-".to_string()
+                });
+                if full.variant.key != "standard" {
+                    tag("Variant", full.variant.name.clone());
+                }
+                if full.initial_fen != "startpos" {
+                    tag("SetUp", "1".to_string());
+                    tag("FEN", self.initial.fen());
+                }
+            }
+            None => {
+                tag("Event", "?".to_string());
+                tag("Site", format!("{}/{}", ENDPOINT_BASE, self.id));
+                tag("Date", "????.??.??".to_string());
+                tag("Round", "-".to_string());
+                tag("White", "?".to_string());
+                tag("Black", "?".to_string());
+                tag("Result", result.clone());
+            }
+        }
+        tag("Termination", termination.to_string());
+
+        let comments = (0..self.moves.len()).map(|ply| {
+            let mut comment = Vec::new();
+            if let Some(eval) = self.evals.get(&ply).filter(|_| self.config.pgn_evals) {
+                comment.push(pgn::eval(*eval));
+            }
+            if let Some(clock) = self.clocks.get(&ply) {
+                comment.push(pgn::clock(*clock));
+            }
+            if comment.is_empty() { None } else { Some(comment.join(" ")) }
+        }).collect();
+
+        Pgn {
+            tags,
+            initial: self.initial.clone(),
+            moves: self.moves.clone(),
+            comments,
+            result,
+        }
+    }
+
+    /// Offering a draw claims it when the rules allow one
+    fn claim_draw(&mut self) {
+        println!("Claiming a draw");
+        post::<Nothing>(ENDPOINT_DRAW, vec!(self.id.clone(), "yes".to_string()), Option::None);
     }
 
     fn resign(&mut self) {
         post::<Nothing>(ENDPOINT_RESIGN, vec!(self.id.clone()), Option::None);
     }
 
-    fn make_move(&mut self) {
+    /// Hashes of the positions the game went through before the current one
+    fn history(&self) -> Vec<u64> {
+        let mut table = self.initial.clone();
+        let mut history = Vec::new();
+        for m in &self.moves {
+            history.push(table.hash());
+            table.process_move(*m);
+        }
+        history
+    }
+
+    fn make_move(&mut self, game_state: &GameStateEvent) {
         let t = SystemTime::now();
+        // Unless the opponent played the move we expected, what was pondered is of no use
+        let ponder = self.ponder.take().filter(|p| p.hash == self.table.hash());
+        let clock = self.full.as_ref().and_then(|full| full.clock.as_ref());
+        let budget = if clock.is_none() {
+            self.time.unlimited()
+        } else if self.my_color == White {
+            self.time.budget(game_state.wtime, game_state.winc, self.table.fullmove_number)
+        } else {
+            self.time.budget(game_state.btime, game_state.binc, self.table.fullmove_number)
+        };
         self.table.print();
+        let history = self.history();
+        match self.table.outcome(&history) {
+            Some(Outcome::Checkmate) | Some(Outcome::Stalemate) => {
+                // Nothing to play, lichess ends the game by itself
+                return;
+            }
+            Some(outcome) => {
+                println!("Draw by {:?} on the board", outcome);
+                // It's our move, so the evaluation is from our side
+                if self.table.evaluate(&mut self.pawns.take()) <= 0 {
+                    self.claim_draw();
+                }
+            }
+            None => {}
+        }
         if self.table.pieces().len() <= 7 {
             // tablebase on
             println!("Tablebase on");
-            let m = get_tablebase_move(&self.table, self.my_color);
-            if m.is_some() && self.send_move(m.unwrap()) {
-                self.table.process_move(m.unwrap());
-                return;
+            if let Some(m) = get_tablebase_move(&self.table) {
+                if self.send_move(m) {
+                    println!("Played {}", self.table.san(m));
+                    self.table.process_move(m);
+                    return;
+                }
             }
         } else if self.table.score(opposite(self.my_color)) - self.table.score(self.my_color) > 20 && self.table.score(self.my_color) < 20 {
             self.resign();
             return;
         }
-        let mut moves: Vec<(i32, Move)> = self.table.available_moves(self.my_color).iter().filter(|x| self.table.is_move_legal(x)).map(|x| (self.table.move_score(*x), *x)).collect();
-        moves.sort_by(|a, b| b.0.cmp(&a.0));
-        let mut i = 0;
-
-        loop {
-            if thread_rng().gen_bool(0.15) && i + 1 < moves.len() {
-                i += 1;
-            }
-            if i >= moves.len() {
-                self.resign();
-                return;
+        let result = match ponder {
+            Some(ponder) => {
+                println!("Ponderhit");
+                ponder.hit(budget)
             }
-            let (v, m) = moves[i];
-            if self.table.assume_move(m).in_check(self.my_color) || !self.send_move(m) {
-                i += 1;
-                moves.remove(moves.iter().position(|x| x == &(v, m)).unwrap_or(0));
-                if i < 100000 {
-                    continue;
+            None => Search::new(&history, self.tt.clone(), &self.pawns, self.config.pruning).run(&self.table, self.config.search_depth, Some(budget), self.threads()),
+        };
+        // The best move first, the others in case lichess refuses it
+        let best = result.pv.first().copied();
+        let mut moves: Vec<Move> = best.into_iter().collect();
+        moves.extend(self.table.legal_moves().iter().filter(|m| Some(**m) != best));
+        for m in moves {
+            let sent = Instant::now();
+            if self.send_move(m) {
+                self.time.record_lag(sent.elapsed());
+                println!("Played {}", self.table.san(m));
+                let mut history = history;
+                history.push(self.table.hash());
+                self.table.process_move(m);
+                let score = if self.my_color == White { result.score } else { -result.score };
+                self.evals.insert(self.moves.len(), score);
+                self.table.print();
+                println!("Elapsed: {}s", t.elapsed().unwrap().as_secs_f64());
+                if let Some(reply) = result.pv.get(1).filter(|_| self.config.ponder && Some(m) == best) {
+                    self.start_pondering(history, *reply);
                 }
+                return;
             }
-            self.table.process_move(m);
-            break;
         }
-        self.table.print();
-        println!("Elapsed: {}s", t.elapsed().unwrap().as_secs_f64());
+        self.resign();
+    }
+
+    /// Threads for a search, every game gets an equal share and at least one
+    fn threads(&self) -> usize {
+        (self.config.threads / ACTIVE_GAMES.load(Ordering::Relaxed).max(1)).max(1)
+    }
+
+    /// Searches the position after `reply`, the move we expect the opponent to answer ours
+    /// with, until they played. `history` holds the positions before the one on the board
+    fn start_pondering(&mut self, mut history: Vec<u64>, reply: Move) {
+        println!("Pondering {}", self.table.san(reply));
+        history.push(self.table.hash());
+        let search = Search::new(&history, self.tt.clone(), &self.pawns, self.config.pruning);
+        let ponder = Ponder::start(search, self.table.assume_move(reply), self.config.search_depth, self.threads());
+        self.ponder = Some(ponder);
     }
 
     fn send_move(&self, m: Move) -> bool {
@@ -491,7 +714,7 @@ struct GameFullEvent {
 }
 
 #[allow(unused)]
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 struct GameStateEvent {
     #[serde(rename = "type")]
     typ: String,
@@ -503,6 +726,7 @@ struct GameStateEvent {
     wdraw: bool,
     bdraw: bool,
     status: String,
+    winner: Option<String>,
 }
 
 #[allow(unused)]
@@ -513,11 +737,93 @@ struct Clock {
 }
 
 #[derive(PartialEq, Clone)]
-struct Table([[Piece; 8]; 8]);
+struct Table {
+    board: [[Piece; 8]; 8],
+    /// The same pieces as `board`, a bitboard for each `Piece as usize`
+    bitboards: [u64; 12],
+    /// All pieces of each color, indexed by `PlayerColor::index`
+    occupancy: [u64; 2],
+    /// Square a pawn skipped over with a double push on the previous move
+    en_passant: Option<Position>,
+    castling: Castling,
+    /// Player to move next
+    turn: PlayerColor,
+    /// Halfmoves since the last capture or pawn move, for the fifty-move rule
+    halfmove_clock: u32,
+    /// Starts at 1 and goes up after every black move
+    fullmove_number: u32,
+    /// Zobrist hash, kept up to date by `set_piece_at` and `process_move`
+    hash: u64,
+    /// Zobrist hash of the pawns alone, kept up to date by `set_piece_at`
+    pawn_hash: u64,
+}
+
+/// Sides each player may still castle to
+#[derive(Copy, Clone, PartialEq)]
+struct Castling {
+    white_king: bool,
+    white_queen: bool,
+    black_king: bool,
+    black_queen: bool,
+}
+
+impl Castling {
+    fn hash(&self) -> u64 {
+        let keys = &zobrist::KEYS.castling;
+        let mut hash = 0;
+        if self.white_king { hash ^= keys[0] }
+        if self.white_queen { hash ^= keys[1] }
+        if self.black_king { hash ^= keys[2] }
+        if self.black_queen { hash ^= keys[3] }
+        hash
+    }
+    /// Drops the rights that depend on a king or rook standing on `pos`,
+    /// called for both squares of every move
+    fn update(&mut self, pos: Position) {
+        match (pos.0, pos.1) {
+            (5, 1) => {
+                self.white_king = false;
+                self.white_queen = false;
+            }
+            (8, 1) => self.white_king = false,
+            (1, 1) => self.white_queen = false,
+            (5, 8) => {
+                self.black_king = false;
+                self.black_queen = false;
+            }
+            (8, 8) => self.black_king = false,
+            (1, 8) => self.black_queen = false,
+            _ => {}
+        }
+    }
+}
+
+impl Default for Castling {
+    fn default() -> Self {
+        Castling {
+            white_king: true,
+            white_queen: true,
+            black_king: true,
+            black_queen: true,
+        }
+    }
+}
+
+impl Display for Castling {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let mut s = "".to_string();
+        if self.white_king { s += "K" }
+        if self.white_queen { s += "Q" }
+        if self.black_king { s += "k" }
+        if self.black_queen { s += "q" }
+        if s.is_empty() { s += "-" }
+        f.write_str(&s)
+    }
+}
 
 impl Default for Table {
     fn default() -> Self {
-        Table([
+        let board = [
             [Piece::WhiteRook, Piece::WhiteKnight, Piece::WhiteBishop, Piece::WhiteKing, Piece::WhiteQueen, Piece::WhiteBishop, Piece::WhiteKnight, Piece::WhiteRook],
             [Piece::WhitePawn, Piece::WhitePawn, Piece::WhitePawn, Piece::WhitePawn, Piece::WhitePawn, Piece::WhitePawn, Piece::WhitePawn, Piece::WhitePawn],
             [Piece::None, Piece::None, Piece::None, Piece::None, Piece::None, Piece::None, Piece::None, Piece::None],
@@ -526,10 +832,78 @@ impl Default for Table {
             [Piece::None, Piece::None, Piece::None, Piece::None, Piece::None, Piece::None, Piece::None, Piece::None],
             [Piece::BlackPawn, Piece::BlackPawn, Piece::BlackPawn, Piece::BlackPawn, Piece::BlackPawn, Piece::BlackPawn, Piece::BlackPawn, Piece::BlackPawn],
             [Piece::BlackRook, Piece::BlackKnight, Piece::BlackBishop, Piece::BlackKing, Piece::BlackQueen, Piece::BlackBishop, Piece::BlackKnight, Piece::BlackRook],
-        ])
+        ];
+        let mut table = Table {
+            board: [[Piece::None; 8]; 8],
+            bitboards: [0; 12],
+            occupancy: [0; 2],
+            en_passant: None,
+            castling: Castling::default(),
+            turn: White,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            hash: 0,
+            pawn_hash: 0,
+        };
+        // Placed one by one to fill the bitboards too
+        for (y, row) in board.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                table.set_piece_at(Position(8 - x as i64, y as i64 + 1), *piece);
+            }
+        }
+        table.hash = table.compute_hash();
+        table
+    }
+}
+
+/// Why a FEN string couldn't be read
+#[derive(Debug)]
+enum FenError {
+    MissingField(&'static str),
+    RankCount(usize),
+    RankLength(usize),
+    UnknownPiece(char),
+    SideToMove(String),
+    Castling(String),
+    EnPassant(String),
+    Counter(String),
+    /// A color doesn't have exactly one king, how many it has
+    KingCount(String, u32),
+    /// Square of a pawn on the first or eighth rank
+    PawnOnBackRank(String),
+    /// The player who just moved left their king in check
+    OpponentInCheck,
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {}", field),
+            FenError::RankCount(n) => write!(f, "expected 8 ranks, got {}", n),
+            FenError::RankLength(rank) => write!(f, "rank {} doesn't have 8 squares", rank),
+            FenError::UnknownPiece(c) => write!(f, "unknown piece '{}'", c),
+            FenError::SideToMove(s) => write!(f, "side to move must be 'w' or 'b', got '{}'", s),
+            FenError::Castling(s) => write!(f, "invalid castling rights '{}'", s),
+            FenError::EnPassant(s) => write!(f, "invalid en passant square '{}'", s),
+            FenError::Counter(s) => write!(f, "invalid move counter '{}'", s),
+            FenError::KingCount(color, n) => write!(f, "{} has {} kings, expected 1", color, n),
+            FenError::PawnOnBackRank(square) => write!(f, "pawn on {}, pawns can't stand on the first or eighth rank", square),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
     }
 }
 
+/// Ways a game ends by the rules alone
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Outcome {
+    /// The player to move is mated
+    Checkmate,
+    Stalemate,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+}
+
 fn color_to_str(color: PlayerColor) -> String {
     if color == White {
         "white"
@@ -541,13 +915,13 @@ fn color_to_str(color: PlayerColor) -> String {
 }
 
 impl Table {
-    fn fen(&self, player_to_move: PlayerColor) -> String {
+    fn fen(&self) -> String {
         let mut s = "".to_string();
 
         // position
         let mut none = 0;
         let mut none_now = false;
-        for row in self.0.iter().rev() {
+        for row in self.board.iter().rev() {
             for piece in row.iter().rev() {
                 if piece == &Piece::None {
                     none_now = true;
@@ -576,40 +950,232 @@ impl Table {
 
         // who moves next
         s += " ";
-        s += if player_to_move == White { "w" } else { "b" };
+        s += if self.turn == White { "w" } else { "b" };
 
-        // castling (castling in endgame?)
-        s += " -";
+        // castling
+        s += " ";
+        s += &*self.castling.to_string();
 
-        // en passant (not now)
-        s += " -";
+        // en passant
+        s += " ";
+        s += &*match self.en_passant {
+            Some(pos) => pos.to_string(),
+            None => "-".to_string()
+        };
 
-        // useless for tablebase
-        s += " 0 1";
+        // halfmove clock and fullmove number
+        s += &*format!(" {} {}", self.halfmove_clock, self.fullmove_number);
 
         s
     }
-    fn in_check(&self, color: PlayerColor) -> bool {
-        let king = if color == White { Piece::WhiteKing } else { Piece::BlackKing };
-        let pieces = self.pieces_colored(color);
-        let d = (Position(0, 0), king);
-        let (king_pos, _) = pieces.iter().find(|(_, p)| p == &king).unwrap_or(&d);
-        let opposite = if color == White { Black } else { White };
-        for (pos, piece) in self.pieces_colored(opposite) {
-            let m = Move::new(pos, king_pos.clone());
-            if piece.moves(pos).contains(&m) && self.is_move_legal(&m) {
-                return true;
+    /// Reads a position from FEN, the move counters are optional
+    fn from_fen(fen: &str) -> Result<Table, FenError> {
+        let mut fields = fen.split_whitespace();
+        let mut table = Table::default();
+
+        // position
+        let ranks: Vec<&str> = fields.next().ok_or(FenError::MissingField("piece placement"))?.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        for (i, row) in ranks.iter().enumerate() {
+            let rank = 8 - i as i64;
+            let mut file = 1;
+            for c in row.chars() {
+                if let Some(n) = c.to_digit(10) {
+                    for _ in 0..n {
+                        if file <= 8 {
+                            table.set_piece_at(Position(file, rank), Piece::None);
+                        }
+                        file += 1;
+                    }
+                } else {
+                    let color = if c.is_ascii_uppercase() { White } else { Black };
+                    let piece = Piece::from_char(c, color).ok_or(FenError::UnknownPiece(c))?;
+                    if file <= 8 {
+                        table.set_piece_at(Position(file, rank), piece);
+                    }
+                    file += 1;
+                }
+            }
+            if file != 9 {
+                return Err(FenError::RankLength(rank as usize));
+            }
+        }
+
+        // who moves next
+        table.turn = match fields.next().ok_or(FenError::MissingField("side to move"))? {
+            "w" => White,
+            "b" => Black,
+            s => return Err(FenError::SideToMove(s.to_string()))
+        };
+
+        // castling
+        let castling = fields.next().ok_or(FenError::MissingField("castling rights"))?;
+        table.castling = Castling { white_king: false, white_queen: false, black_king: false, black_queen: false };
+        if castling != "-" {
+            for c in castling.chars() {
+                match c {
+                    'K' => table.castling.white_king = true,
+                    'Q' => table.castling.white_queen = true,
+                    'k' => table.castling.black_king = true,
+                    'q' => table.castling.black_queen = true,
+                    _ => return Err(FenError::Castling(castling.to_string()))
+                }
+            }
+        }
+
+        // en passant
+        let en_passant = fields.next().ok_or(FenError::MissingField("en passant square"))?;
+        table.en_passant = if en_passant == "-" {
+            None
+        } else {
+            // Behind a pawn the other side just pushed two squares, over two empty squares
+            let (rank, forward) = if table.turn == White { (6, 1) } else { (3, -1) };
+            let pawn = Piece::WhitePawn.with_color(opposite(table.turn));
+            match Position::parse(en_passant) {
+                Some(pos) if pos.1 == rank && table.get_piece_at(pos.add(0, -forward)) == pawn
+                    && table.get_piece_at(pos) == Piece::None && table.get_piece_at(pos.add(0, forward)) == Piece::None => Some(pos),
+                _ => return Err(FenError::EnPassant(en_passant.to_string()))
+            }
+        };
+
+        // halfmove clock and fullmove number
+        if let Some(counter) = fields.next() {
+            table.halfmove_clock = counter.parse().map_err(|_| FenError::Counter(counter.to_string()))?;
+        }
+        if let Some(counter) = fields.next() {
+            table.fullmove_number = counter.parse().map_err(|_| FenError::Counter(counter.to_string()))?;
+        }
+
+        // only positions a game can reach, the rest would trip up move generation and evaluation
+        for color in [White, Black].iter() {
+            let kings = table.bitboard(Piece::WhiteKing.with_color(*color)).count_ones();
+            if kings != 1 {
+                return Err(FenError::KingCount(color_to_str(*color), kings));
+            }
+        }
+        let back_ranks = (table.bitboard(Piece::WhitePawn) | table.bitboard(Piece::BlackPawn)) & (0xFF | 0xFF << 56);
+        if back_ranks != 0 {
+            return Err(FenError::PawnOnBackRank(Position::from_index(back_ranks.trailing_zeros() as usize).to_string()));
+        }
+        if table.in_check(opposite(table.turn)) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        table.hash = table.compute_hash();
+        Ok(table)
+    }
+    /// Whether the game is over by the rules, given the positions before this one
+    fn outcome(&self, history: &[u64]) -> Option<Outcome> {
+        if !self.has_legal_move() {
+            return Some(if self.in_check(self.turn) { Outcome::Checkmate } else { Outcome::Stalemate });
+        }
+        if self.halfmove_clock >= 100 {
+            return Some(Outcome::FiftyMoveRule);
+        }
+        if self.insufficient_material() {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        if self.repetitions(history) >= 2 {
+            return Some(Outcome::ThreefoldRepetition);
+        }
+        None
+    }
+    /// How many times this position was already on the board
+    fn repetitions(&self, history: &[u64]) -> usize {
+        // Positions can only repeat since the last capture or pawn move
+        let recent = &history[history.len().saturating_sub(self.halfmove_clock as usize)..];
+        let hash = self.hash();
+        recent.iter().filter(|h| **h == hash).count()
+    }
+    /// Identifies the position in the sense of the repetition rule, move counters don't matter
+    fn hash(&self) -> u64 {
+        self.hash
+    }
+    /// Hashes the position from scratch, `hash` is the same but cheaper
+    fn compute_hash(&self) -> u64 {
+        let mut hash = self.state_hash();
+        for square in Squares(self.occupied()) {
+            let pos = Position::from_index(square);
+            hash ^= piece_hash(pos, self.get_piece_at(pos));
+        }
+        hash
+    }
+    fn compute_pawn_hash(&self) -> u64 {
+        let mut hash = 0;
+        for piece in [Piece::WhitePawn, Piece::BlackPawn].iter() {
+            for square in Squares(self.bitboard(*piece)) {
+                hash ^= piece_hash(Position::from_index(square), *piece);
+            }
+        }
+        hash
+    }
+    /// Hash of everything besides the pieces
+    fn state_hash(&self) -> u64 {
+        let mut hash = self.castling.hash();
+        // Only when a pawn can take, otherwise the position is the same as without the square
+        if let Some(pos) = self.en_passant {
+            let takers = PAWN_ATTACKS[opposite(self.turn).index()][pos.index()] & self.bitboard(Piece::WhitePawn.with_color(self.turn));
+            if takers != 0 {
+                hash ^= zobrist::KEYS.en_passant[pos.0 as usize - 1];
+            }
+        }
+        if self.turn == Black {
+            hash ^= zobrist::KEYS.black_to_move;
+        }
+        hash
+    }
+    fn has_legal_move(&self) -> bool {
+        self.available_moves(self.turn).iter().any(|m| self.is_legal(m))
+    }
+    /// Whether neither side can possibly mate: lone kings, a single minor piece,
+    /// or only bishops all on squares of one color
+    fn insufficient_material(&self) -> bool {
+        let pieces: Vec<(Position, Piece)> = self.pieces().into_iter().filter(|(_, p)| p.value() != Piece::WhiteKing.value()).collect();
+        match pieces.as_slice() {
+            [] => true,
+            [(_, p)] => p.value() == 3,
+            _ => {
+                let bishops_on = |square_color| pieces.iter().all(|(pos, p)| {
+                    (*p == Piece::WhiteBishop || *p == Piece::BlackBishop) && (pos.0 + pos.1) % 2 == square_color
+                });
+                bishops_on(0) || bishops_on(1)
             }
         }
-        false
+    }
+    fn in_check(&self, color: PlayerColor) -> bool {
+        let king = self.bitboard(Piece::WhiteKing.with_color(color));
+        king != 0 && self.is_attacked(Position::from_index(king.trailing_zeros() as usize), opposite(color))
+    }
+    /// Whether any piece of `by` attacks `pos`, regardless of what stands there
+    fn is_attacked(&self, pos: Position, by: PlayerColor) -> bool {
+        self.attackers(pos.index(), by, self.occupied()) != 0
+    }
+    /// Pieces of `by` attacking a square, sliders see through anything not in `occupied`
+    fn attackers(&self, square: usize, by: PlayerColor, occupied: u64) -> u64 {
+        let bishops = self.bitboard(Piece::WhiteBishop.with_color(by)) | self.bitboard(Piece::WhiteQueen.with_color(by));
+        let rooks = self.bitboard(Piece::WhiteRook.with_color(by)) | self.bitboard(Piece::WhiteQueen.with_color(by));
+        // Pawns take diagonally forward, so they stand where a pawn of the other color would take
+        PAWN_ATTACKS[opposite(by).index()][square] & self.bitboard(Piece::WhitePawn.with_color(by))
+            | KNIGHT_ATTACKS[square] & self.bitboard(Piece::WhiteKnight.with_color(by))
+            | KING_ATTACKS[square] & self.bitboard(Piece::WhiteKing.with_color(by))
+            | bishop_attacks(square, occupied) & bishops
+            | rook_attacks(square, occupied) & rooks
+    }
+    fn bitboard(&self, piece: Piece) -> u64 {
+        if piece == Piece::None { 0 } else { self.bitboards[piece as usize] }
+    }
+    fn occupied(&self) -> u64 {
+        self.occupancy[0] | self.occupancy[1]
     }
     fn score(&self, color: PlayerColor) -> i32 {
-        self.pieces_colored(color).iter().map(|x| x.1.value()).sum()
+        PIECES.iter().filter(|p| p.color() == color).map(|p| self.bitboard(*p).count_ones() as i32 * p.value()).sum()
     }
     fn print(&self) {
         let mut white = true;
         let mut str = "".to_string();
-        self.0.iter().for_each(|row| {
+        self.board.iter().for_each(|row| {
             row.iter().for_each(|piece| {
                 let mut s = piece.to_string();
                 s = piece.color().color().bold().on(if white { Color::Fixed(253) } else { Color::Fixed(238) }).paint(format!(" {} ", s)).to_string();
@@ -622,218 +1188,263 @@ impl Table {
         println!("{}", str)
     }
     fn pieces(&self) -> Vec<(Position, Piece)> {
-        let mut pieces = Vec::new();
-
-        for x in 1..9 {
-            for y in 1..9 {
-                let pos = Position(x, y);
-                let piece = self.get_piece_at(pos);
-                if piece != Piece::None {
-                    pieces.push((pos, piece));
+        Squares(self.occupied()).map(Position::from_index).map(|pos| (pos, self.get_piece_at(pos))).collect()
+    }
+    /// Replays `moves` from `initial`. Where the game left off can't be told from the position,
+    /// it may have been on the board before
+    fn sync(&mut self, initial: &Table, moves: &[Move]) {
+        *self = initial.clone();
+        for m in moves {
+            self.process_move(*m);
+        }
+    }
+    /// Moves of `color` by the rules of each piece, they may leave the own king in check
+    fn available_moves(&self, color: PlayerColor) -> MoveList {
+        let mut moves = MoveList::new();
+        for from in Squares(self.occupancy[color.index()]) {
+            let pos = Position::from_index(from);
+            let piece = self.get_piece_at(pos);
+            let promotes = piece == Piece::WhitePawn && pos.1 == 7 || piece == Piece::BlackPawn && pos.1 == 2;
+            for to in Squares(self.targets(pos, piece)) {
+                if promotes {
+                    for p in color.promotions().iter() {
+                        moves.push(Move::with_promotion(pos, Position::from_index(to), *p));
+                    }
+                } else {
+                    moves.push(Move::new(pos, Position::from_index(to)));
                 }
             }
         }
-        pieces
+        moves
     }
-    fn sync(&mut self, moves: &Vec<Move>) {
-        let mut table = Table::default();
-        let mut syncing = false;
-        if table.0 == self.0 {
-            syncing = true
-        }
-        for m in moves {
-            table.process_move(*m);
-            if syncing {
-                self.process_move(*m);
-            }
-            if table.0 == self.0 {
-                syncing = true
-            }
+    /// Moves of the side to move that don't leave its own king in check
+    fn legal_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        for m in self.available_moves(self.turn).iter().filter(|m| self.is_legal(m)) {
+            moves.push(*m);
         }
+        moves
     }
-    fn pieces_colored(&self, color: PlayerColor) -> Vec<(Position, Piece)> {
-        self.pieces().iter_mut().filter(|p| p.1.color() == color).map(|p| *p).collect()
+    /// Whether `m` takes a piece, en passant included
+    fn is_capture(&self, m: &Move) -> bool {
+        self.get_piece_at(m.b) != Piece::None || self.is_en_passant(m)
     }
-    fn available_moves(&self, color: PlayerColor) -> Vec<Move> {
-        let mut moves = Vec::new();
-        let a = self.pieces_colored(color);
-        for (pos, piece) in a {
-            let mut p = piece.moves(pos);
-            moves.append(&mut p);
-        }
-        moves.iter().filter(|m| self.is_move_legal(*m)).map(|m| *m).collect()
+    /// Whether a move from `available_moves` keeps the own king out of check,
+    /// which covers pins, check evasions and en passant uncovering a check
+    fn is_legal(&self, m: &Move) -> bool {
+        !self.assume_move(*m).in_check(self.get_piece_at(m.a).color())
     }
-    fn is_move_legal(&self, m: &Move) -> bool {
-        let pos = m.a;
-        let piece = self.get_piece_at(pos);
+    /// Squares `piece` standing on `pos` may go to
+    fn targets(&self, pos: Position, piece: Piece) -> u64 {
         let color = piece.color();
-        let mut legal = true;
-        if !m.b.valid() || !m.a.valid() || self.get_piece_at(m.b).color() == color {
-            legal = false
-        }
-        let dist_x = m.b.0 - m.a.0;
-        let dist_y = m.b.1 - m.a.1;
-        let dir_x = dist_x.signum();
-        let dir_y = dist_y.signum();
-        match piece {
-            Piece::None => legal = false,
+        let square = pos.index();
+        let occupied = self.occupied();
+        let targets = match piece {
+            Piece::None => 0,
             Piece::WhiteKing | Piece::BlackKing => {
-                if max(dist_x.abs(), dist_y.abs()) != 1 {
-                    legal = false
-                }
-            }
-            Piece::WhiteQueen | Piece::BlackQueen => {
-                if dir_x == 0 || dir_y == 0 {
-                    // Like a rook
-                    for i in 1..dist_x.abs() {
-                        let i = dir_x * i;
-                        if self.get_piece_at(pos.add(i, 0)) != Piece::None {
-                            legal = false;
-                        }
-                    }
-                    for i in 1..dist_y.abs() {
-                        let i = dir_y * i;
-                        if self.get_piece_at(pos.add(0, i)) != Piece::None {
-                            legal = false;
-                        }
-                    }
-                } else {
-                    // Like a bishop
-                    for i in 1..dist_x.abs() {
-                        let x = dir_x * i;
-                        let y = dir_y * i;
-                        if self.get_piece_at(pos.add(x, y)) != Piece::None {
-                            legal = false;
-                        }
+                let mut targets = KING_ATTACKS[square];
+                for x in [2, -2].iter() {
+                    let m = Move::new(pos, pos.add(*x, 0));
+                    if m.b.valid() && self.can_castle(&m) {
+                        targets |= 1 << m.b.index();
                     }
                 }
+                targets
             }
-            Piece::WhiteRook | Piece::BlackRook => {
-                for i in 1..dist_x.abs() {
-                    let i = dir_x * i;
-                    if self.get_piece_at(pos.add(i, 0)) != Piece::None {
-                        legal = false;
+            Piece::WhiteQueen | Piece::BlackQueen => bishop_attacks(square, occupied) | rook_attacks(square, occupied),
+            Piece::WhiteRook | Piece::BlackRook => rook_attacks(square, occupied),
+            Piece::WhiteKnight | Piece::BlackKnight => KNIGHT_ATTACKS[square],
+            Piece::WhiteBishop | Piece::BlackBishop => bishop_attacks(square, occupied),
+            Piece::WhitePawn | Piece::BlackPawn => {
+                // Captures, either onto an enemy piece or en passant
+                let mut targets = PAWN_ATTACKS[color.index()][square] & self.occupancy[opposite(color).index()];
+                if let Some(ep) = self.en_passant {
+                    if PAWN_ATTACKS[color.index()][square] & 1 << ep.index() != 0 && self.is_en_passant(&Move::new(pos, ep)) {
+                        targets |= 1 << ep.index();
                     }
                 }
-                for i in 1..dist_y.abs() {
-                    let i = dir_y * i;
-                    if self.get_piece_at(pos.add(0, i)) != Piece::None {
-                        legal = false;
+                // Pushes never take anything
+                let forward = if color == White { 1 } else { -1 };
+                let start = if color == White { 2 } else { 7 };
+                let one = pos.add(0, forward);
+                if one.valid() && self.get_piece_at(one) == Piece::None {
+                    targets |= 1 << one.index();
+                    let two = pos.add(0, forward * 2);
+                    if pos.1 == start && self.get_piece_at(two) == Piece::None {
+                        targets |= 1 << two.index();
                     }
                 }
+                targets
             }
-            Piece::WhiteKnight | Piece::BlackKnight => {}
-            Piece::WhiteBishop | Piece::BlackBishop => {
-                for i in 1..dist_x.abs() {
-                    let x = dir_x * i;
-                    let y = dir_y * i;
-                    if self.get_piece_at(pos.add(x, y)) != Piece::None {
-                        legal = false;
-                    }
-                }
-            }
-            Piece::WhitePawn => {
-                if dir_y == -1 {
-                    legal = false
-                }
-                if dir_x != 0 && self.get_piece_at(pos.add(dir_x, dist_y)) == Piece::None {
-                    legal = false
-                }
-                if dist_x == 0 && self.get_piece_at(pos.add(0, 1)) != Piece::None {
-                    legal = false
-                }
-                if dist_y == 2 && pos.1 != 2 {
-                    legal = false
-                }
+        };
+        targets & !self.occupancy[color.index()]
+    }
+    /// Whether the king move `m` is a castling the player still has the right to,
+    /// with nothing between king and rook and the king not passing through check
+    fn can_castle(&self, m: &Move) -> bool {
+        let color = self.get_piece_at(m.a).color();
+        let rank = if color == White { 1 } else { 8 };
+        if m.a != Position(5, rank) || m.b.1 != rank {
+            return false;
+        }
+        let king_side = m.b.0 == 7;
+        let right = match (color, king_side) {
+            (White, true) => self.castling.white_king,
+            (White, false) => self.castling.white_queen,
+            (_, true) => self.castling.black_king,
+            (_, false) => self.castling.black_queen,
+        };
+        let rook = if color == White { Piece::WhiteRook } else { Piece::BlackRook };
+        let (rook_file, between) = if king_side { (8, 6..8) } else { (1, 2..5) };
+        if !right || self.get_piece_at(Position(rook_file, rank)) != rook {
+            return false;
+        }
+        for x in between {
+            if self.get_piece_at(Position(x, rank)) != Piece::None {
+                return false;
             }
-            Piece::BlackPawn => {
-                if dir_y == 1 {
-                    legal = false
-                }
-                if dir_x != 0 && self.get_piece_at(pos.add(dir_x, dir_y)) == Piece::None {
-                    legal = false
-                }
-                if dist_x == 0 && self.get_piece_at(pos.add(0, dir_y)) != Piece::None {
-                    legal = false
-                }
-                if dist_y == -2 && pos.1 != 7 {
-                    legal = false
-                }
+        }
+        // Landing in check is left to the caller, like for any other move
+        let dir = if king_side { 1 } else { -1 };
+        !self.is_attacked(m.a, opposite(color)) && !self.is_attacked(m.a.add(dir, 0), opposite(color))
+    }
+    /// Whether `m` is a pawn taking en passant
+    fn is_en_passant(&self, m: &Move) -> bool {
+        let piece = self.get_piece_at(m.a);
+        match self.en_passant {
+            Some(ep) if ep == m.b && m.a.0 != m.b.0 => {
+                piece == Piece::WhitePawn && ep.1 == 6 || piece == Piece::BlackPawn && ep.1 == 3
             }
+            _ => false
         }
-        legal
     }
     fn assume_move(&self, m: Move) -> Table {
         let mut table = self.clone();
         table.process_move(m);
         table
     }
+    /// The position with the side to move passing, for the search to see what the
+    /// other side could do with a free move
+    fn null_move(&self) -> Table {
+        let mut table = self.clone();
+        table.hash ^= table.state_hash();
+        table.en_passant = None;
+        table.turn = opposite(table.turn);
+        // Nothing before a pass counts as a repetition
+        table.halfmove_clock = 0;
+        table.hash ^= table.state_hash();
+        table
+    }
+    /// Whether `color` has anything besides pawns and the king
+    fn has_pieces(&self, color: PlayerColor) -> bool {
+        let pawns_and_king = self.bitboard(Piece::WhitePawn.with_color(color)) | self.bitboard(Piece::WhiteKing.with_color(color));
+        self.occupancy[color.index()] & !pawns_and_king != 0
+    }
     fn get_piece_at(&self, pos: Position) -> Piece {
-        self.0[pos.1 as usize - 1][7 - (pos.0 as usize - 1)]
+        self.board[pos.1 as usize - 1][7 - (pos.0 as usize - 1)]
     }
     fn set_piece_at(&mut self, pos: Position, piece: Piece) {
-        self.0[pos.1 as usize - 1][7 - (pos.0 as usize - 1)] = piece;
+        let old = self.get_piece_at(pos);
+        let bit = 1 << pos.index();
+        if old != Piece::None {
+            self.bitboards[old as usize] ^= bit;
+            self.occupancy[old.color().index()] ^= bit;
+        }
+        if piece != Piece::None {
+            self.bitboards[piece as usize] ^= bit;
+            self.occupancy[piece.color().index()] ^= bit;
+        }
+        self.hash ^= piece_hash(pos, old) ^ piece_hash(pos, piece);
+        if old.is_pawn() {
+            self.pawn_hash ^= piece_hash(pos, old);
+        }
+        if piece.is_pawn() {
+            self.pawn_hash ^= piece_hash(pos, piece);
+        }
+        self.board[pos.1 as usize - 1][7 - (pos.0 as usize - 1)] = piece;
     }
 
     fn process_move(&mut self, m: Move) {
         let mut piece = self.get_piece_at(m.a);
+        self.hash ^= self.state_hash();
+
+        // Move counters
+        if piece.value() == 1 || self.get_piece_at(m.b) != Piece::None {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.turn == Black {
+            self.fullmove_number += 1;
+        }
+        self.turn = opposite(self.turn);
+
+        // En passant, the taken pawn is behind the target square
+        if self.is_en_passant(&m) {
+            self.set_piece_at(Position(m.b.0, m.a.1), Piece::None);
+        }
+        self.en_passant = if (piece == Piece::WhitePawn || piece == Piece::BlackPawn) && (m.b.1 - m.a.1).abs() == 2 {
+            Some(Position(m.a.0, (m.a.1 + m.b.1) / 2))
+        } else {
+            None
+        };
 
-        // Pawn promotion (for now, queen only)
+        // Pawn promotion, to a queen if the move doesn't say
         if piece.value() == 1 && (m.b.1 == 8 || m.b.1 == 1) {
-            piece = if piece.color() == White { Piece::WhiteQueen } else { Piece::BlackQueen };
+            piece = m.promotion.unwrap_or(if piece.color() == White { Piece::WhiteQueen } else { Piece::BlackQueen });
         }
 
         // Castling
         if piece == Piece::WhiteKing {
             if m.a == Position::from_str("e1") && m.b == Position::from_str("c1") {
-                self.process_move(Move::from_str("a1d1"));
+                self.set_piece_at(Position::from_str("d1"), Piece::WhiteRook);
+                self.set_piece_at(Position::from_str("a1"), Piece::None);
             }
             if m.a == Position::from_str("e1") && m.b == Position::from_str("g1") {
-                self.process_move(Move::from_str("h1f1"));
+                self.set_piece_at(Position::from_str("f1"), Piece::WhiteRook);
+                self.set_piece_at(Position::from_str("h1"), Piece::None);
             }
         }
         if piece == Piece::BlackKing {
             if m.a == Position::from_str("e8") && m.b == Position::from_str("c8") {
-                self.process_move(Move::from_str("a8d8"));
+                self.set_piece_at(Position::from_str("d8"), Piece::BlackRook);
+                self.set_piece_at(Position::from_str("a8"), Piece::None);
             }
             if m.a == Position::from_str("e8") && m.b == Position::from_str("g8") {
-                self.process_move(Move::from_str("h8f8"));
+                self.set_piece_at(Position::from_str("f8"), Piece::BlackRook);
+                self.set_piece_at(Position::from_str("h8"), Piece::None);
             }
         }
+        self.castling.update(m.a);
+        self.castling.update(m.b);
 
         self.set_piece_at(m.b, piece);
         self.set_piece_at(m.a, Piece::None);
+        self.hash ^= self.state_hash();
+        debug_assert_eq!(self.hash, self.compute_hash());
+        debug_assert_eq!(self.pawn_hash, self.compute_pawn_hash());
     }
-    fn move_score(&self, m: Move) -> i32 {
+    /// Positions before this one are needed to see repetitions
+    fn move_score(&self, m: Move, history: &[u64]) -> i32 {
         let mut score = 0;
         let piece = self.get_piece_at(m.a);
         let color = piece.color();
         let opp_color = opposite(color);
         let t = self.assume_move(m);
 
-        if t.in_check(color) {
-            return -1;
-        }
-
         // take
         if self.get_piece_at(m.b).color() == opp_color {
             score += self.get_piece_at(m.b).value() * 17;
+        } else if self.is_en_passant(&m) {
+            score += Piece::WhitePawn.value() * 17;
         }
 
-        // attack
-        for m2 in t.available_moves(color) {
-            if m2.a == m.b && self.get_piece_at(m2.b).color() == opp_color {
-                let mut can_defend = false;
-                let t = t.assume_move(m2);
-                for m3 in t.available_moves(opp_color) {
-                    if m3.b == m2.b {
-                        can_defend = true;
-                        break;
-                    }
-                }
-                if !can_defend {
-                    score += self.get_piece_at(m2.b).value() * 2;
-                }
+        // attack, pieces the moved one would win taking next
+        let moved = t.get_piece_at(m.b);
+        for target in Squares(t.targets(m.b, moved) & t.occupancy[opp_color.index()]) {
+            let target = Position::from_index(target);
+            if t.see(Move::new(m.b, target)) > 0 {
+                score += t.get_piece_at(target).value() * 2;
             }
         }
 
@@ -842,19 +1453,29 @@ impl Table {
             score += 10;
         }
 
-        // giveaway
-        for m2 in t.available_moves(opp_color) {
-            if t.get_piece_at(m2.b).color() == color {
-                let t = t.assume_move(m2);
-                let mut defended = false;
-                for m3 in t.available_moves(color) {
-                    if m3.b == m2.b && t.get_piece_at(m2.a).value() > piece.value() {
-                        defended = true;
-                    }
-                }
-                if !defended {
-                    score -= piece.value() * 18;
-                }
+        // giveaway, material the exchange on the target square loses
+        let see = self.see(m);
+        if see < 0 {
+            score += see * 18 / 100;
+        }
+
+        // promotion
+        if let Some(p) = m.promotion {
+            score += (p.value() - piece.value()) * 17;
+        }
+
+        // castling
+        if (piece == Piece::WhiteKing || piece == Piece::BlackKing) && (m.b.0 - m.a.0).abs() == 2 {
+            score += 10;
+        }
+
+        // game over or repetition, a draw is only good for the one who is behind
+        let balance = self.score(color) - self.score(opp_color);
+        match t.outcome(history) {
+            Some(Outcome::Checkmate) => score += 1000,
+            Some(_) => score -= balance.signum() * 100,
+            None => if t.repetitions(history) > 0 {
+                score -= balance.signum() * 30;
             }
         }
 
@@ -867,6 +1488,14 @@ impl Table {
     }
 }
 
+fn piece_hash(pos: Position, piece: Piece) -> u64 {
+    if piece == Piece::None {
+        0
+    } else {
+        zobrist::KEYS.pieces[piece as usize][pos.index()]
+    }
+}
+
 fn opposite(color: PlayerColor) -> PlayerColor {
     if color == White { Black } else { White }
 }
@@ -888,6 +1517,12 @@ enum Piece {
     None,
 }
 
+/// Every piece but `Piece::None`, in the order of `Piece as usize`
+const PIECES: [Piece; 12] = [
+    Piece::BlackKing, Piece::BlackQueen, Piece::BlackRook, Piece::BlackKnight, Piece::BlackBishop, Piece::BlackPawn,
+    Piece::WhiteKing, Piece::WhiteQueen, Piece::WhiteRook, Piece::WhiteKnight, Piece::WhiteBishop, Piece::WhitePawn,
+];
+
 impl Display for Piece {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         f.write_str(match self {
@@ -903,6 +1538,19 @@ impl Display for Piece {
 }
 
 impl Piece {
+    /// Parses a piece letter, as in FEN or UCI promotions, ignoring its case
+    fn from_char(c: char, color: PlayerColor) -> Option<Piece> {
+        let white = color == White;
+        Some(match c.to_ascii_lowercase() {
+            'k' => if white { Piece::WhiteKing } else { Piece::BlackKing },
+            'q' => if white { Piece::WhiteQueen } else { Piece::BlackQueen },
+            'r' => if white { Piece::WhiteRook } else { Piece::BlackRook },
+            'n' => if white { Piece::WhiteKnight } else { Piece::BlackKnight },
+            'b' => if white { Piece::WhiteBishop } else { Piece::BlackBishop },
+            'p' => if white { Piece::WhitePawn } else { Piece::BlackPawn },
+            _ => return None
+        })
+    }
     fn value(&self) -> i32 {
         match self {
             Piece::None => 0,
@@ -914,6 +1562,9 @@ impl Piece {
             Piece::WhitePawn | Piece::BlackPawn => 1,
         }
     }
+    fn is_pawn(&self) -> bool {
+        *self == Piece::WhitePawn || *self == Piece::BlackPawn
+    }
     fn color(&self) -> PlayerColor {
         match self {
             Piece::None => Unknown,
@@ -931,108 +1582,13 @@ impl Piece {
             Piece::WhitePawn => White
         }
     }
-    fn moves(&self, pos: Position) -> Vec<Move> {
-        let mut moves = Vec::new();
-        match self {
-            Piece::None => (),
-            Piece::WhiteKing | Piece::BlackKing => {
-                moves.push(Move::new(pos, pos.add(1, 0)));
-                moves.push(Move::new(pos, pos.add(1, 1)));
-                moves.push(Move::new(pos, pos.add(0, 1)));
-                moves.push(Move::new(pos, pos.add(-1, 0)));
-                moves.push(Move::new(pos, pos.add(-1, -1)));
-                moves.push(Move::new(pos, pos.add(0, -1)));
-                moves.push(Move::new(pos, pos.add(-1, 1)));
-                moves.push(Move::new(pos, pos.add(1, -1)));
-            }
-            Piece::WhiteQueen | Piece::BlackQueen => {
-                moves.append(&mut Piece::WhiteBishop.moves(pos));
-                moves.append(&mut Piece::WhiteRook.moves(pos));
-            }
-            Piece::WhiteRook | Piece::BlackRook => {
-                moves.push(Move::new(pos, pos.add(0, 1)));
-                moves.push(Move::new(pos, pos.add(0, 2)));
-                moves.push(Move::new(pos, pos.add(0, 3)));
-                moves.push(Move::new(pos, pos.add(0, 4)));
-                moves.push(Move::new(pos, pos.add(0, 5)));
-                moves.push(Move::new(pos, pos.add(0, 6)));
-                moves.push(Move::new(pos, pos.add(0, 7)));
-                moves.push(Move::new(pos, pos.add(0, -1)));
-                moves.push(Move::new(pos, pos.add(0, -2)));
-                moves.push(Move::new(pos, pos.add(0, -3)));
-                moves.push(Move::new(pos, pos.add(0, -4)));
-                moves.push(Move::new(pos, pos.add(0, -5)));
-                moves.push(Move::new(pos, pos.add(0, -6)));
-                moves.push(Move::new(pos, pos.add(0, -7)));
-                moves.push(Move::new(pos, pos.add(1, 0)));
-                moves.push(Move::new(pos, pos.add(2, 0)));
-                moves.push(Move::new(pos, pos.add(3, 0)));
-                moves.push(Move::new(pos, pos.add(4, 0)));
-                moves.push(Move::new(pos, pos.add(5, 0)));
-                moves.push(Move::new(pos, pos.add(6, 0)));
-                moves.push(Move::new(pos, pos.add(7, 0)));
-                moves.push(Move::new(pos, pos.add(-1, 0)));
-                moves.push(Move::new(pos, pos.add(-2, 0)));
-                moves.push(Move::new(pos, pos.add(-3, 0)));
-                moves.push(Move::new(pos, pos.add(-4, 0)));
-                moves.push(Move::new(pos, pos.add(-5, 0)));
-                moves.push(Move::new(pos, pos.add(-6, 0)));
-                moves.push(Move::new(pos, pos.add(-7, 0)));
-            }
-            Piece::WhiteKnight | Piece::BlackKnight => {
-                moves.push(Move::new(pos, pos.add(1, 2)));
-                moves.push(Move::new(pos, pos.add(2, 1)));
-                moves.push(Move::new(pos, pos.add(1, -2)));
-                moves.push(Move::new(pos, pos.add(2, -1)));
-                moves.push(Move::new(pos, pos.add(-1, 2)));
-                moves.push(Move::new(pos, pos.add(-2, 1)));
-                moves.push(Move::new(pos, pos.add(-1, -2)));
-                moves.push(Move::new(pos, pos.add(-2, -1)));
-            }
-            Piece::WhiteBishop | Piece::BlackBishop => {
-                moves.push(Move::new(pos, pos.add(1, 1)));
-                moves.push(Move::new(pos, pos.add(2, 2)));
-                moves.push(Move::new(pos, pos.add(3, 3)));
-                moves.push(Move::new(pos, pos.add(4, 4)));
-                moves.push(Move::new(pos, pos.add(5, 5)));
-                moves.push(Move::new(pos, pos.add(6, 6)));
-                moves.push(Move::new(pos, pos.add(7, 7)));
-                moves.push(Move::new(pos, pos.add(1, -1)));
-                moves.push(Move::new(pos, pos.add(2, -2)));
-                moves.push(Move::new(pos, pos.add(3, -3)));
-                moves.push(Move::new(pos, pos.add(4, -4)));
-                moves.push(Move::new(pos, pos.add(5, -5)));
-                moves.push(Move::new(pos, pos.add(6, -6)));
-                moves.push(Move::new(pos, pos.add(7, -7)));
-                moves.push(Move::new(pos, pos.add(-1, 1)));
-                moves.push(Move::new(pos, pos.add(-2, 2)));
-                moves.push(Move::new(pos, pos.add(-3, 3)));
-                moves.push(Move::new(pos, pos.add(-4, 4)));
-                moves.push(Move::new(pos, pos.add(-5, 5)));
-                moves.push(Move::new(pos, pos.add(-6, 6)));
-                moves.push(Move::new(pos, pos.add(-7, 7)));
-                moves.push(Move::new(pos, pos.add(-1, -1)));
-                moves.push(Move::new(pos, pos.add(-2, -2)));
-                moves.push(Move::new(pos, pos.add(-3, -3)));
-                moves.push(Move::new(pos, pos.add(-4, -4)));
-                moves.push(Move::new(pos, pos.add(-5, -5)));
-                moves.push(Move::new(pos, pos.add(-6, -6)));
-                moves.push(Move::new(pos, pos.add(-7, -7)));
-            }
-            Piece::WhitePawn => {
-                moves.push(Move::new(pos, pos.add(0, 1)));
-                moves.push(Move::new(pos, pos.add(0, 2)));
-                moves.push(Move::new(pos, pos.add(1, 1)));
-                moves.push(Move::new(pos, pos.add(-1, 1)));
-            }
-            Piece::BlackPawn => {
-                moves.push(Move::new(pos, pos.add(0, -1)));
-                moves.push(Move::new(pos, pos.add(0, -2)));
-                moves.push(Move::new(pos, pos.add(1, -1)));
-                moves.push(Move::new(pos, pos.add(-1, -1)));
-            }
-        };
-        moves.iter().filter(|m| m.a.valid() && m.b.valid()).map(|m| *m).collect()
+    /// The same kind of piece in `color`
+    fn with_color(&self, color: PlayerColor) -> Piece {
+        match (*self, color) {
+            (Piece::None, _) | (_, Unknown) => *self,
+            (piece, White) => PIECES[piece as usize % 6 + 6],
+            (piece, Black) => PIECES[piece as usize % 6],
+        }
     }
 }
 
@@ -1040,24 +1596,68 @@ impl Piece {
 struct Move {
     a: Position,
     b: Position,
+    /// Piece a pawn turns into on the last rank
+    promotion: Option<Piece>,
 }
 
 impl Move {
-    pub fn new(a: Position, b: Position) -> Move { Move { a, b } }
+    pub fn new(a: Position, b: Position) -> Move { Move { a, b, promotion: None } }
+    pub fn with_promotion(a: Position, b: Position, piece: Piece) -> Move { Move { a, b, promotion: Some(piece) } }
     pub fn from_str(s: &str) -> Move {
-        Move::new(Position::from_str(&s[0..2]), Position::from_str(&s[2..4]))
+        let a = Position::from_str(&s[0..2]);
+        let b = Position::from_str(&s[2..4]);
+        match s[4..].chars().next().and_then(|c| Piece::from_char(c, Move::promoting_color(b))) {
+            Some(piece) => Move::with_promotion(a, b, piece),
+            None => Move::new(a, b)
+        }
+    }
+    /// Color of a pawn promoting on `to`, for move formats that leave it out
+    pub fn promoting_color(to: Position) -> PlayerColor {
+        // Only white promotes on the 8th rank
+        if to.1 == 8 { White } else { Black }
+    }
+}
+
+/// Moves of one position, kept on the stack
+struct MoveList {
+    moves: [Move; 256],
+    len: usize,
+}
+
+impl MoveList {
+    fn new() -> MoveList {
+        MoveList {
+            moves: [Move::new(Position(1, 1), Position(1, 1)); 256],
+            len: 0,
+        }
+    }
+    fn push(&mut self, m: Move) {
+        self.moves[self.len] = m;
+        self.len += 1;
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
     }
 }
 
 impl Debug for Move {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        f.write_str(&*(self.a.to_string() + &*self.b.to_string()))
+        Display::fmt(self, f)
     }
 }
 
 impl Display for Move {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        f.write_str(&*(self.a.to_string() + &*self.b.to_string()))
+        f.write_str(&*(self.a.to_string() + &*self.b.to_string()))?;
+        if let Some(piece) = self.promotion {
+            f.write_str(&piece.to_string().to_lowercase())?;
+        }
+        Ok(())
     }
 }
 
@@ -1067,12 +1667,21 @@ struct Position(i64, i64);
 impl Position {
     fn valid(&self) -> bool { self.0 >= 1 && self.0 <= 8 && self.1 >= 1 && self.1 <= 8 }
     fn add(&self, x: i64, y: i64) -> Position { Position(self.0 + x, self.1 + y) }
+    /// 0 for a1, 1 for b1 up to 63 for h8
+    fn index(&self) -> usize { ((self.1 - 1) * 8 + self.0 - 1) as usize }
+    fn from_index(index: usize) -> Position { Position(index as i64 % 8 + 1, index as i64 / 8 + 1) }
     fn from_str(s: &str) -> Position {
-        let ch1 = &s[0..1];
-        let ch2 = &s[1..2];
-        let x = A1.iter().position(|x| x == &ch1).unwrap();
-        let y = A2.iter().position(|x| x == &ch2).unwrap();
-        Position(x as i64 + 1, y as i64 + 1)
+        Position::parse(s).unwrap()
+    }
+    fn parse(s: &str) -> Option<Position> {
+        if s.len() != 2 {
+            return None;
+        }
+        let ch1 = s.get(0..1)?;
+        let ch2 = s.get(1..2)?;
+        let x = A1.iter().position(|x| x == &ch1)?;
+        let y = A2.iter().position(|x| x == &ch2)?;
+        Some(Position(x as i64 + 1, y as i64 + 1))
     }
 }
 
@@ -1080,4 +1689,103 @@ impl Display for Position {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         f.write_str(&*(A1[self.0 as usize - 1].to_string() + &A2[self.1 as usize - 1].to_string()))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{FenError, Move, Outcome, Table};
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 13 42",
+        ].iter() {
+            assert_eq!(Table::from_fen(fen).unwrap().fen(), *fen);
+        }
+    }
+
+    #[test]
+    fn sync_through_repetition() {
+        let moves: Vec<Move> = "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8 e2e4".split_whitespace().map(Move::from_str).collect();
+        let initial = Table::default();
+        let mut table = initial.clone();
+        // One event per ply, as lichess sends them
+        for ply in 0..=moves.len() {
+            table.sync(&initial, &moves[..ply]);
+        }
+        assert_eq!(table.fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 5");
+        table.sync(&initial, &moves[..4]);
+        assert_eq!(table.fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3");
+    }
+
+    #[test]
+    fn repetition_after_double_push() {
+        let mut table = Table::default();
+        let mut history = Vec::new();
+        for m in "e2e4 e7e5 g1f3 b8c6 f3g1 c6b8 g1f3 b8c6 f3g1 c6b8".split_whitespace() {
+            history.push(table.hash());
+            table.process_move(Move::from_str(m));
+        }
+        // The position after 1...e5 counts although its en passant square was set the first time
+        assert_eq!(table.repetitions(&history), 2);
+        assert_eq!(table.outcome(&history), Some(Outcome::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn outcomes() {
+        let outcome = |fen: &str| Table::from_fen(fen).unwrap().outcome(&[]);
+        assert_eq!(outcome("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"), Some(Outcome::Checkmate));
+        assert_eq!(outcome("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(Outcome::Stalemate));
+        assert_eq!(outcome("4k3/8/8/8/8/8/4P3/R3K3 w - - 100 80"), Some(Outcome::FiftyMoveRule));
+        assert_eq!(outcome("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80"), None);
+        // Mate on the last move beats the fifty-move rule
+        assert_eq!(outcome("R3k3/8/4K3/8/8/8/8/8 b - - 100 80"), Some(Outcome::Checkmate));
+        assert_eq!(outcome("4k3/8/8/8/8/8/8/4K3 w - - 0 1"), Some(Outcome::InsufficientMaterial));
+        assert_eq!(outcome("4k3/8/8/8/8/8/8/4KN2 w - - 0 1"), Some(Outcome::InsufficientMaterial));
+        assert_eq!(outcome("4k3/8/8/8/8/8/8/4KB2 w - - 0 1"), Some(Outcome::InsufficientMaterial));
+        // Bishops all on dark squares, of either side
+        assert_eq!(outcome("4kb2/8/8/8/8/8/8/2B1K1B1 w - - 0 1"), Some(Outcome::InsufficientMaterial));
+        assert_eq!(outcome("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1"), None);
+        assert_eq!(outcome("4k3/8/8/8/8/8/8/4KNN1 w - - 0 1"), None);
+        assert_eq!(outcome("4k3/8/8/8/8/8/8/4KBN1 w - - 0 1"), None);
+        assert_eq!(outcome("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut table = Table::default();
+        let mut history = Vec::new();
+        for (ply, m) in "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8".split_whitespace().enumerate() {
+            assert_eq!(table.outcome(&history), None, "ply {}", ply);
+            history.push(table.hash());
+            table.process_move(Move::from_str(m));
+        }
+        assert_eq!(table.repetitions(&history), 2);
+        assert_eq!(table.outcome(&history), Some(Outcome::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn fen_errors() {
+        let error = |fen: &str| Table::from_fen(fen).err().expect(fen);
+        assert!(matches!(error("8/8/8/8/8/8/8 w - - 0 1"), FenError::RankCount(7)));
+        assert!(matches!(error("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), FenError::RankLength(7)));
+        assert!(matches!(error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1"), FenError::UnknownPiece('X')));
+        assert!(matches!(error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1"), FenError::SideToMove(_)));
+        assert!(matches!(error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQxq - 0 1"), FenError::Castling(_)));
+        assert!(matches!(error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e5 0 1"), FenError::EnPassant(_)));
+        assert!(matches!(error("4k3/8/8/3Pn3/8/8/8/4K3 w - e6 0 1"), FenError::EnPassant(square) if square == "e6"));
+        assert!(matches!(error("4k3/8/8/3Pp3/8/8/8/4K3 b - e6 0 1"), FenError::EnPassant(_)));
+        assert!(matches!(error("4k3/4r3/8/3Pp3/8/8/8/4K3 w - e6 0 1"), FenError::EnPassant(_)));
+        assert!(Table::from_fen("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1").is_ok());
+        assert!(matches!(error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1"), FenError::Counter(_)));
+        assert!(matches!(error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w"), FenError::MissingField(_)));
+        assert!(matches!(error("8/8/8/8/8/8/8/8 w - - 0 1"), FenError::KingCount(_, 0)));
+        assert!(matches!(error("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"), FenError::KingCount(_, 2)));
+        assert!(matches!(error("4k3/8/8/8/8/8/8/4K2p w - - 0 1"), FenError::PawnOnBackRank(square) if square == "h1"));
+        assert!(matches!(error("P3k3/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::PawnOnBackRank(square) if square == "a8"));
+        assert!(matches!(error("4k2R/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::OpponentInCheck));
+        assert!(Table::from_fen("4k2R/8/8/8/8/8/8/4K3 b - - 0 1").is_ok());
+    }
+}
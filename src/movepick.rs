@@ -0,0 +1,208 @@
+//! Hands out the moves of a position best first, one stage at a time, so a search that
+//! cuts off early doesn't pay for generating and sorting the moves it never tries
+
+use crate::{Move, Piece, Table};
+
+/// How often quiet moves caused a cutoff, by `Piece as usize` and target square
+pub struct History([[i32; 64]; 12]);
+
+/// Scores are halved when one gets this high, so older cutoffs count less
+const HISTORY_MAX: i32 = 1 << 20;
+
+impl History {
+    pub fn new() -> History {
+        History([[0; 64]; 12])
+    }
+    fn score(&self, table: &Table, m: Move) -> i32 {
+        self.0[table.get_piece_at(m.a) as usize][m.b.index()]
+    }
+    /// A quiet move cut the search `depth` plies deep, deeper cuts count more
+    pub fn reward(&mut self, table: &Table, m: Move, depth: u32) {
+        let score = &mut self.0[table.get_piece_at(m.a) as usize][m.b.index()];
+        *score += (depth * depth) as i32;
+        if *score >= HISTORY_MAX {
+            for scores in self.0.iter_mut() {
+                for score in scores.iter_mut() {
+                    *score /= 2;
+                }
+            }
+        }
+    }
+}
+
+/// Two quiet moves per ply that caused a cutoff, likely to cut again in the positions next to it
+pub struct Killers(Vec<[Option<Move>; 2]>);
+
+impl Killers {
+    pub fn new() -> Killers {
+        Killers(Vec::new())
+    }
+    pub fn get(&self, ply: usize) -> [Option<Move>; 2] {
+        self.0.get(ply).copied().unwrap_or([None, None])
+    }
+    pub fn store(&mut self, ply: usize, m: Move) {
+        if self.0.len() <= ply {
+            self.0.resize(ply + 1, [None, None]);
+        }
+        let killers = &mut self.0[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Stage {
+    Best,
+    GenerateCaptures,
+    GoodCaptures,
+    Killer(usize),
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+pub struct MovePicker<'a> {
+    table: &'a Table,
+    stage: Stage,
+    /// From the transposition table or the previous iteration
+    best: Option<Move>,
+    killers: [Option<Move>; 2],
+    /// Moves of the current stage and their scores
+    moves: Vec<(i32, Move)>,
+    /// Captures losing material by static exchange, for the last stage
+    bad_captures: Vec<(i32, Move)>,
+    /// Stops after the captures that don't lose material, for quiescence
+    captures_only: bool,
+}
+
+impl<'a> MovePicker<'a> {
+    /// Every legal move: `best` first, then captures winning or keeping material, the
+    /// killers, the quiet moves by history and last the captures losing material
+    pub fn new(table: &'a Table, best: Option<Move>, killers: [Option<Move>; 2]) -> MovePicker<'a> {
+        MovePicker {
+            table,
+            stage: Stage::Best,
+            best,
+            killers,
+            moves: Vec::new(),
+            bad_captures: Vec::new(),
+            captures_only: false,
+        }
+    }
+
+    /// Legal captures and promotions that don't lose material, most valuable victim first
+    pub fn captures(table: &'a Table) -> MovePicker<'a> {
+        MovePicker {
+            stage: Stage::GenerateCaptures,
+            captures_only: true,
+            ..MovePicker::new(table, None, [None, None])
+        }
+    }
+
+    /// The next legal move, `history` orders the quiet ones
+    pub fn next(&mut self, history: &History) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::Best => {
+                    self.stage = Stage::GenerateCaptures;
+                    if let Some(m) = self.best.filter(|m| self.is_valid(*m)) {
+                        return Some(m);
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    for m in self.table.available_moves(self.table.turn).iter() {
+                        if !self.is_tactical(*m) || Some(*m) == self.best {
+                            continue;
+                        }
+                        let see = self.table.see(*m);
+                        if see >= 0 {
+                            self.moves.push((mvv_lva(self.table, *m), *m));
+                        } else if !self.captures_only {
+                            self.bad_captures.push((see, *m));
+                        }
+                    }
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => match self.pick() {
+                    Some(m) => return Some(m),
+                    None => self.stage = if self.captures_only { Stage::Done } else { Stage::Killer(0) }
+                },
+                Stage::Killer(i) => {
+                    self.stage = if i == 0 { Stage::Killer(1) } else { Stage::GenerateQuiets };
+                    if let Some(m) = self.killers[i] {
+                        if Some(m) != self.best && !self.is_tactical(m) && self.is_valid(m) {
+                            return Some(m);
+                        }
+                    }
+                }
+                Stage::GenerateQuiets => {
+                    for m in self.table.available_moves(self.table.turn).iter() {
+                        if self.is_tactical(*m) || Some(*m) == self.best || self.killers.contains(&Some(*m)) {
+                            continue;
+                        }
+                        self.moves.push((history.score(self.table, *m), *m));
+                    }
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => match self.pick() {
+                    Some(m) => return Some(m),
+                    None => {
+                        self.moves = std::mem::take(&mut self.bad_captures);
+                        self.stage = Stage::BadCaptures;
+                    }
+                },
+                Stage::BadCaptures => match self.pick() {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::Done
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// Takes the highest scored legal move out of the current stage
+    fn pick(&mut self) -> Option<Move> {
+        while !self.moves.is_empty() {
+            let mut best = 0;
+            for i in 1..self.moves.len() {
+                if self.moves[i].0 > self.moves[best].0 {
+                    best = i;
+                }
+            }
+            let (_, m) = self.moves.swap_remove(best);
+            if self.table.is_legal(&m) {
+                return Some(m);
+            }
+        }
+        None
+    }
+
+    fn is_tactical(&self, m: Move) -> bool {
+        self.table.is_capture(&m) || m.promotion.is_some()
+    }
+
+    /// Whether a move from somewhere else, like another position with the same hash
+    /// or a sibling's killer, is legal here
+    fn is_valid(&self, m: Move) -> bool {
+        let piece = self.table.get_piece_at(m.a);
+        if piece.color() != self.table.turn || self.table.targets(m.a, piece) & 1 << m.b.index() == 0 {
+            return false;
+        }
+        let last_rank = piece == Piece::WhitePawn && m.b.1 == 8 || piece == Piece::BlackPawn && m.b.1 == 1;
+        let promotes = match m.promotion {
+            Some(p) => last_rank && piece.color().promotions().contains(&p),
+            None => !last_rank,
+        };
+        promotes && self.table.is_legal(&m)
+    }
+}
+
+/// Most valuable victim first, and of those the one taken by the least valuable attacker
+fn mvv_lva(table: &Table, m: Move) -> i32 {
+    let victim = if table.is_en_passant(&m) { Piece::WhitePawn } else { table.get_piece_at(m.b) };
+    let promotion = m.promotion.map_or(0, |p| p.value());
+    (victim.value() + promotion) * 100 - table.get_piece_at(m.a).value()
+}
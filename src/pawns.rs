@@ -0,0 +1,309 @@
+//! Pawn structure: doubled, isolated, backward, connected and passed pawns. Pawns move
+//! seldom, so what they're worth is cached by the hash of the pawns alone
+
+use crate::bitboard::{Squares, PAWN_ATTACKS};
+use crate::{opposite, Black, Piece, PlayerColor, Position, Table, White};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Centipawns, middlegame and endgame, for each pawn on a file with more of its own pawns
+const DOUBLED: (i32, i32) = (-10, -20);
+/// With no pawns of its own on the files beside it
+const ISOLATED: (i32, i32) = (-10, -15);
+/// Behind the pawns beside it and kept from advancing by an enemy pawn
+const BACKWARD: (i32, i32) = (-8, -10);
+/// Defended by or standing next to a pawn of its own, by rank counted from its own side
+const CONNECTED: [i32; 8] = [0, 0, 4, 6, 10, 18, 30, 0];
+/// No enemy pawn can stop it, and none of its own stands in front, by rank
+const PASSED_MG: [i32; 8] = [0, 5, 10, 15, 25, 40, 60, 0];
+const PASSED_EG: [i32; 8] = [0, 10, 15, 25, 45, 70, 110, 0];
+/// In the endgame, per square between a king and the square in front of a passed pawn, for
+/// each rank the pawn is past its third
+const ENEMY_KING_DISTANCE: i32 = 5;
+const OWN_KING_DISTANCE: i32 = 2;
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+const FILE_H: u64 = FILE_A << 7;
+
+/// Entries of each pawn table, one per search thread
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+/// What the pawns of a position are worth, from white's side
+#[derive(Copy, Clone, Default)]
+pub struct PawnEntry {
+    key: u64,
+    pub mg: i32,
+    pub eg: i32,
+    /// Passed pawns of both colors, their worth also depends on where the kings are
+    pub passed: u64,
+}
+
+/// Cache of pawn structures by `Table::pawn_hash`, a position without pawns needs no
+/// entry of its own as the empty one scores nothing
+pub struct PawnTable(Vec<PawnEntry>);
+
+impl PawnTable {
+    pub fn new() -> PawnTable {
+        PawnTable(vec![PawnEntry::default(); PAWN_TABLE_SIZE])
+    }
+
+    pub fn get(&mut self, table: &Table) -> PawnEntry {
+        let index = table.pawn_hash as usize & (PAWN_TABLE_SIZE - 1);
+        if self.0[index].key != table.pawn_hash {
+            self.0[index] = PawnEntry { key: table.pawn_hash, ..table.pawn_structure() };
+        }
+        self.0[index]
+    }
+}
+
+/// The pawn tables of a game, kept between its searches like the transposition table.
+/// Each search thread borrows one of its own
+#[derive(Clone)]
+pub struct PawnTables(Arc<Mutex<Vec<PawnTable>>>);
+
+impl PawnTables {
+    pub fn new() -> PawnTables {
+        PawnTables(Arc::new(Mutex::new(Vec::new())))
+    }
+
+    /// One no other thread is using, a new one if they all are
+    pub fn take(&self) -> BorrowedPawnTable {
+        let table = self.0.lock().unwrap().pop().unwrap_or_else(PawnTable::new);
+        BorrowedPawnTable { table: Some(table), tables: self.clone() }
+    }
+}
+
+/// Goes back to its `PawnTables` when dropped
+pub struct BorrowedPawnTable {
+    table: Option<PawnTable>,
+    tables: PawnTables,
+}
+
+impl BorrowedPawnTable {
+    /// Where it came from, for more threads of the same search
+    pub fn tables(&self) -> &PawnTables {
+        &self.tables
+    }
+}
+
+impl Deref for BorrowedPawnTable {
+    type Target = PawnTable;
+
+    fn deref(&self) -> &PawnTable {
+        self.table.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for BorrowedPawnTable {
+    fn deref_mut(&mut self) -> &mut PawnTable {
+        self.table.as_mut().unwrap()
+    }
+}
+
+impl Drop for BorrowedPawnTable {
+    fn drop(&mut self) {
+        if let Some(table) = self.table.take() {
+            self.tables.0.lock().unwrap().push(table);
+        }
+    }
+}
+
+impl Table {
+    fn pawn_structure(&self) -> PawnEntry {
+        let mut entry = PawnEntry::default();
+        for color in [White, Black].iter() {
+            let sign = if *color == White { 1 } else { -1 };
+            let ours = self.bitboard(Piece::WhitePawn.with_color(*color));
+            let theirs = self.bitboard(Piece::WhitePawn.with_color(opposite(*color)));
+            let (mut mg, mut eg) = (0, 0);
+            for square in Squares(ours) {
+                let file = square % 8;
+                let rank = relative_rank(square, *color);
+                let beside = adjacent_files(file);
+
+                if ours & FILE_A << file & !(1 << square) != 0 {
+                    mg += DOUBLED.0;
+                    eg += DOUBLED.1;
+                }
+                if ours & beside == 0 {
+                    mg += ISOLATED.0;
+                    eg += ISOLATED.1;
+                } else if ours & beside & !ahead(square, *color) == 0 {
+                    // Every pawn beside it went ahead, none can defend the square in front
+                    let stop = if *color == White { square + 8 } else { square - 8 };
+                    if PAWN_ATTACKS[color.index()][stop] & theirs != 0 {
+                        mg += BACKWARD.0;
+                        eg += BACKWARD.1;
+                    }
+                }
+
+                let defenders = PAWN_ATTACKS[opposite(*color).index()][square] & ours;
+                let phalanx = ((1 << square) << 1 & !FILE_A | (1 << square) >> 1 & !FILE_H) & ours;
+                if defenders | phalanx != 0 {
+                    mg += CONNECTED[rank];
+                    eg += CONNECTED[rank];
+                }
+
+                let front = ahead(square, *color);
+                if theirs & front & (beside | FILE_A << file) == 0 && ours & front & FILE_A << file == 0 {
+                    mg += PASSED_MG[rank];
+                    eg += PASSED_EG[rank];
+                    entry.passed |= 1 << square;
+                }
+            }
+            entry.mg += sign * mg;
+            entry.eg += sign * eg;
+        }
+        entry
+    }
+
+    /// Endgame bonus from white's side of having the own king close to passed pawns and the
+    /// enemy king far from them
+    pub fn passed_pawn_kings(&self, passed: u64) -> i32 {
+        let mut eg = 0;
+        for square in Squares(passed) {
+            let color = self.get_piece_at(Position::from_index(square)).color();
+            let sign = if color == White { 1 } else { -1 };
+            let weight = (relative_rank(square, color) as i32 - 2).max(0);
+            let stop = if color == White { square + 8 } else { square - 8 };
+            let own = self.bitboard(Piece::WhiteKing.with_color(color));
+            let enemy = self.bitboard(Piece::WhiteKing.with_color(opposite(color)));
+            if own == 0 || enemy == 0 {
+                continue;
+            }
+            let own = distance(own.trailing_zeros() as usize, stop);
+            let enemy = distance(enemy.trailing_zeros() as usize, stop);
+            eg += sign * weight * (enemy * ENEMY_KING_DISTANCE - own * OWN_KING_DISTANCE);
+        }
+        eg
+    }
+}
+
+/// 0 to 7, counted from the side of `color`
+fn relative_rank(square: usize, color: PlayerColor) -> usize {
+    if color == White { square / 8 } else { 7 - square / 8 }
+}
+
+fn adjacent_files(file: usize) -> u64 {
+    let file = FILE_A << file;
+    (file << 1 & !FILE_A) | (file >> 1 & !FILE_H)
+}
+
+/// Every square on the ranks in front of `square`, as `color` moves
+fn ahead(square: usize, color: PlayerColor) -> u64 {
+    let rank = square / 8;
+    if color == White {
+        if rank == 7 { 0 } else { !0 << (8 * (rank + 1)) }
+    } else {
+        (1 << (8 * rank)) - 1
+    }
+}
+
+/// King moves from one square to the other
+fn distance(a: usize, b: usize) -> i32 {
+    let files = (a % 8) as i32 - (b % 8) as i32;
+    let ranks = (a / 8) as i32 - (b / 8) as i32;
+    files.abs().max(ranks.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Move;
+
+    fn structure(fen: &str) -> PawnEntry {
+        Table::from_fen(fen).unwrap().pawn_structure()
+    }
+
+    /// The same position with the colors swapped and the board upside down
+    fn mirror(fen: &str) -> String {
+        let fields: Vec<&str> = fen.split(' ').collect();
+        let swap_case = |s: &str| s.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect::<String>();
+        let board: Vec<String> = fields[0].split('/').rev().map(swap_case).collect();
+        let turn = if fields[1] == "w" { "b" } else { "w" };
+        let en_passant = fields[3].replace('3', "x").replace('6', "3").replace('x', "6");
+        format!("{} {} {} {} {} {}", board.join("/"), turn, swap_case(fields[2]), en_passant, fields[4], fields[5])
+    }
+
+    #[test]
+    fn doubled_and_isolated() {
+        // Two white pawns on the a-file against one black, none of them passed
+        let entry = structure("4k3/p7/8/8/8/P7/P7/4K3 w - - 0 1");
+        assert_eq!(entry.mg, 2 * (DOUBLED.0 + ISOLATED.0) - ISOLATED.0);
+        assert_eq!(entry.eg, 2 * (DOUBLED.1 + ISOLATED.1) - ISOLATED.1);
+        assert_eq!(entry.passed, 0);
+    }
+
+    #[test]
+    fn backward() {
+        // d3 can't move up without being taken and c4 went ahead, which defends c4 in turn.
+        // Black's pawns are isolated
+        let entry = structure("4k3/8/8/2p1p3/2P5/3P4/8/4K3 w - - 0 1");
+        assert_eq!(entry.mg, BACKWARD.0 + CONNECTED[3] - 2 * ISOLATED.0);
+        assert_eq!(entry.eg, BACKWARD.1 + CONNECTED[3] - 2 * ISOLATED.1);
+        assert_eq!(entry.passed, 0);
+    }
+
+    #[test]
+    fn connected_and_passed() {
+        let entry = structure("4k3/8/8/8/4PP2/8/8/4K3 w - - 0 1");
+        assert_eq!(entry.mg, 2 * (CONNECTED[3] + PASSED_MG[3]));
+        assert_eq!(entry.eg, 2 * (CONNECTED[3] + PASSED_EG[3]));
+        assert_eq!(entry.passed, 1 << 28 | 1 << 29);
+        // A pawn on the file beside stops it as well
+        assert_eq!(structure("4k3/3p4/8/8/4P3/8/8/4K3 w - - 0 1").passed, 0);
+        // Black's count from the other side
+        let entry = structure("4k3/8/8/8/8/7p/8/4K3 w - - 0 1");
+        assert_eq!(entry.mg, -(ISOLATED.0 + PASSED_MG[5]));
+        assert_eq!(entry.eg, -(ISOLATED.1 + PASSED_EG[5]));
+        assert_eq!(entry.passed, 1 << 23);
+    }
+
+    #[test]
+    fn passed_pawn_kings() {
+        // e6 is 3 ranks past the third, its own king 2 squares from e7 and the enemy one 4
+        let table = Table::from_fen("k7/8/4P3/4K3/8/8/8/8 w - - 0 1").unwrap();
+        let passed = table.pawn_structure().passed;
+        assert_eq!(table.passed_pawn_kings(passed), 3 * (4 * ENEMY_KING_DISTANCE - 2 * OWN_KING_DISTANCE));
+        let table = Table::from_fen(&mirror("k7/8/4P3/4K3/8/8/8/8 w - - 0 1")).unwrap();
+        let passed = table.pawn_structure().passed;
+        assert_eq!(table.passed_pawn_kings(passed), -3 * (4 * ENEMY_KING_DISTANCE - 2 * OWN_KING_DISTANCE));
+        // Not yet past the third rank
+        let table = Table::from_fen("k7/8/8/8/8/4P3/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(table.passed_pawn_kings(table.pawn_structure().passed), 0);
+    }
+
+    #[test]
+    fn cached_like_computed() {
+        let mut table = Table::default();
+        let mut pawns = PawnTable::new();
+        // Pawn moves, captures, en passant and a promotion, every position looked up twice
+        let moves = "e2e4 d7d5 e4d5 c7c5 d5c6 b7c6 g1f3 e7e5 f3e5 d8d2 b1d2 c6c5 b2b4 c5b4 a2a3 b4a3 c1b2 a3b2 h2h4 b2a1q";
+        for m in moves.split_whitespace() {
+            table.process_move(Move::from_str(m));
+            for _ in 0..2 {
+                let cached = pawns.get(&table);
+                let computed = table.pawn_structure();
+                assert_eq!(cached.key, table.pawn_hash, "{}", m);
+                assert_eq!((cached.mg, cached.eg, cached.passed), (computed.mg, computed.eg, computed.passed), "{}", m);
+            }
+        }
+    }
+
+    #[test]
+    fn evaluation_mirrors() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/5k2/4p3/1p1pP3/1P1P4/3K4/8/8 b - - 0 40",
+            "6k1/1P6/8/8/8/8/5p2/1K6 w - - 0 60",
+        ];
+        let mut pawns = PawnTable::new();
+        for fen in fens.iter() {
+            let table = Table::from_fen(fen).unwrap();
+            let mirrored = Table::from_fen(&mirror(fen)).unwrap();
+            assert_eq!(table.evaluate(&mut pawns), mirrored.evaluate(&mut pawns), "{}", fen);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Perft counts the move sequences of a given length from a position, comparing
+//! it with known numbers is how move generation bugs get found
+
+use crate::{Move, Table};
+use std::time::Instant;
+
+impl Table {
+    /// Number of legal move sequences `depth` plies long
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.iter().map(|m| self.assume_move(*m).perft(depth - 1)).sum()
+    }
+    /// Perft split by the first move, to narrow down where a count goes wrong
+    pub fn perft_divide(&self, depth: u32) -> Vec<(Move, u64)> {
+        self.legal_moves().iter().map(|m| (*m, self.assume_move(*m).perft(depth.saturating_sub(1)))).collect()
+    }
+}
+
+/// `perft <depth> [fen]`, prints the divide and the total
+pub fn run(args: &[String]) {
+    let depth = match args.first().map(|d| d.parse::<u32>()) {
+        Some(Ok(depth)) if depth > 0 => depth,
+        _ => {
+            println!("Usage: perft <depth> [fen]");
+            return;
+        }
+    };
+    let table = if args.len() > 1 {
+        match Table::from_fen(&args[1..].join(" ")) {
+            Ok(table) => table,
+            Err(err) => {
+                println!("Invalid FEN: {}", err);
+                return;
+            }
+        }
+    } else {
+        Table::default()
+    };
+
+    let t = Instant::now();
+    let divide = table.perft_divide(depth);
+    for (m, nodes) in &divide {
+        println!("{}: {}", m, nodes);
+    }
+    let nodes: u64 = divide.iter().map(|(_, nodes)| nodes).sum();
+    let elapsed = t.elapsed().as_secs_f64();
+    println!();
+    println!("Nodes: {}", nodes);
+    println!("Elapsed: {}s ({:.0} nodes/s)", elapsed, nodes as f64 / elapsed);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Table;
+
+    // Positions and counts from https://www.chessprogramming.org/Perft_Results
+    fn perft(fen: &str, depth: u32) -> u64 {
+        Table::from_fen(fen).unwrap().perft(depth)
+    }
+
+    #[test]
+    fn start_position() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(perft(fen, 1), 20);
+        assert_eq!(perft(fen, 2), 400);
+        assert_eq!(perft(fen, 3), 8902);
+        assert_eq!(perft(fen, 4), 197281);
+    }
+
+    #[test]
+    fn kiwipete() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(perft(fen, 1), 48);
+        assert_eq!(perft(fen, 2), 2039);
+        assert_eq!(perft(fen, 3), 97862);
+    }
+
+    #[test]
+    fn position_3() {
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        assert_eq!(perft(fen, 1), 14);
+        assert_eq!(perft(fen, 2), 191);
+        assert_eq!(perft(fen, 3), 2812);
+        assert_eq!(perft(fen, 4), 43238);
+    }
+
+    #[test]
+    fn position_4() {
+        let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        assert_eq!(perft(fen, 1), 6);
+        assert_eq!(perft(fen, 2), 264);
+        assert_eq!(perft(fen, 3), 9467);
+    }
+
+    #[test]
+    fn position_4_mirrored() {
+        let fen = "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+        assert_eq!(perft(fen, 1), 6);
+        assert_eq!(perft(fen, 2), 264);
+        assert_eq!(perft(fen, 3), 9467);
+    }
+
+    #[test]
+    fn position_5() {
+        let fen = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+        assert_eq!(perft(fen, 1), 44);
+        assert_eq!(perft(fen, 2), 1486);
+        assert_eq!(perft(fen, 3), 62379);
+    }
+
+    #[test]
+    fn position_6() {
+        let fen = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+        assert_eq!(perft(fen, 1), 46);
+        assert_eq!(perft(fen, 2), 2079);
+        assert_eq!(perft(fen, 3), 89890);
+    }
+}
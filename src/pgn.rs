@@ -0,0 +1,491 @@
+//! Portable Game Notation, the text format games are saved and shared in
+
+use crate::pawns::PawnTable;
+use crate::search::{MATE, MATE_BOUND};
+use crate::{FenError, Move, PlayerColor, Table};
+use std::fmt::{Display, Error, Formatter};
+use std::fs;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A game with its tags and the moves of its main line
+pub struct Pgn {
+    /// Tag name and value, the seven tag roster comes first
+    pub tags: Vec<(String, String)>,
+    /// Position the moves start from
+    pub initial: Table,
+    pub moves: Vec<Move>,
+    /// Comment after each move, by ply
+    pub comments: Vec<Option<String>>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*` while the game goes on
+    pub result: String,
+}
+
+/// Lines of movetext are kept within this many characters
+const LINE_LENGTH: usize = 80;
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+impl Pgn {
+    fn new() -> Pgn {
+        Pgn {
+            tags: Vec::new(),
+            initial: Table::default(),
+            moves: Vec::new(),
+            comments: Vec::new(),
+            result: "*".to_string(),
+        }
+    }
+    /// Value of the first tag called `name`
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+    /// Every position of the game, from the initial one to the one after the last move
+    pub fn tables(&self) -> Vec<Table> {
+        let mut table = self.initial.clone();
+        let mut tables = vec![table.clone()];
+        for m in &self.moves {
+            table.process_move(*m);
+            tables.push(table.clone());
+        }
+        tables
+    }
+}
+
+/// Why PGN text couldn't be read
+#[derive(Debug)]
+pub enum PgnError {
+    /// A tag that isn't `[Name "value"]`
+    Tag(String),
+    Fen(FenError),
+    /// A move that isn't legal, or is ambiguous, in its position
+    IllegalMove(String),
+    /// A comment, variation or tag that never ends
+    Unclosed(char),
+    /// A `)` without its `(`
+    Unopened(char),
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            PgnError::Tag(s) => write!(f, "invalid tag '{}'", s),
+            PgnError::Fen(err) => write!(f, "invalid FEN tag: {}", err),
+            PgnError::IllegalMove(s) => write!(f, "illegal move {}", s),
+            PgnError::Unclosed(c) => write!(f, "'{}' is never closed", c),
+            PgnError::Unopened(c) => write!(f, "'{}' without its opening bracket", c),
+        }
+    }
+}
+
+/// Reads every game in `text`, variations are skipped and only the main line is kept,
+/// comments stay with the move before them, NAGs and annotations like `!?` are dropped
+pub fn read(text: &str) -> Result<Vec<Pgn>, PgnError> {
+    let mut games = Vec::new();
+    let mut game = Pgn::new();
+    // Position after the moves read so far, set up when the movetext starts
+    let mut table: Option<Table> = None;
+    let mut depth = 0;
+    let mut line_start = true;
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        let at_line_start = line_start;
+        line_start = c == '\n';
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            // Escaped lines are for other programs
+            '%' if at_line_start => {
+                until(&mut chars, '\n');
+                line_start = true;
+            }
+            '[' if depth == 0 => {
+                chars.next();
+                // Tags after movetext start the next game
+                if table.is_some() {
+                    games.push(game);
+                    game = Pgn::new();
+                    table = None;
+                }
+                let tag = until(&mut chars, ']').ok_or(PgnError::Unclosed('['))?;
+                game.tags.push(parse_tag(&tag)?);
+            }
+            '{' | ';' => {
+                chars.next();
+                let comment = if c == '{' { until(&mut chars, '}').ok_or(PgnError::Unclosed('{'))? } else { until(&mut chars, '\n').unwrap_or_default() };
+                let comment = comment.split_whitespace().collect::<Vec<&str>>().join(" ");
+                if depth == 0 && !game.moves.is_empty() && !comment.is_empty() {
+                    let last = game.comments.last_mut().unwrap();
+                    *last = Some(match last.take() {
+                        Some(before) => before + " " + &comment,
+                        None => comment
+                    });
+                }
+                line_start = c == ';';
+            }
+            '(' => {
+                chars.next();
+                depth += 1;
+            }
+            ')' => {
+                chars.next();
+                if depth == 0 {
+                    return Err(PgnError::Unopened(')'));
+                }
+                depth -= 1;
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}()[];".contains(c) {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                if token.is_empty() {
+                    // A stray bracket, nothing to read from it
+                    chars.next();
+                    continue;
+                }
+                if depth > 0 {
+                    continue;
+                }
+                if RESULTS.contains(&&*token) {
+                    if table.is_none() {
+                        set_up(&mut game)?;
+                    }
+                    game.result = token;
+                    games.push(std::mem::replace(&mut game, Pgn::new()));
+                    table = None;
+                    continue;
+                }
+                let table = match &mut table {
+                    Some(table) => table,
+                    None => table.insert(set_up(&mut game)?)
+                };
+                // Move numbers like `12.` or `12...` may stick to the move, castling written
+                // with zeros like `0-0` starts with a digit too
+                let number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+                let san = if number.is_empty() || number.starts_with('.') { number.trim_start_matches('.') } else { &token };
+                if san.is_empty() || san.starts_with('$') || san.chars().all(|c| c == '!' || c == '?') {
+                    continue;
+                }
+                match table.parse_san(san) {
+                    Some(m) => {
+                        game.moves.push(m);
+                        game.comments.push(None);
+                        table.process_move(m);
+                    }
+                    None => return Err(PgnError::IllegalMove(format!("{} in {}", san, table.fen())))
+                }
+            }
+        }
+    }
+    if depth > 0 {
+        return Err(PgnError::Unclosed('('));
+    }
+    if table.is_some() || !game.tags.is_empty() {
+        if table.is_none() {
+            set_up(&mut game)?;
+        }
+        games.push(game);
+    }
+    for game in &mut games {
+        if game.result == "*" {
+            if let Some(result) = game.tag("Result").filter(|r| RESULTS.contains(r)) {
+                game.result = result.to_string();
+            }
+        }
+    }
+    Ok(games)
+}
+
+/// Starts the game from its FEN tag if it has one, and gives the position its moves start from
+fn set_up(game: &mut Pgn) -> Result<Table, PgnError> {
+    if let Some(fen) = game.tag("FEN") {
+        game.initial = Table::from_fen(fen).map_err(PgnError::Fen)?;
+    }
+    Ok(game.initial.clone())
+}
+
+/// Text up to `end`, which is skipped, `None` if it never comes
+fn until(chars: &mut Peekable<Chars>, end: char) -> Option<String> {
+    let mut s = String::new();
+    let mut quoted = false;
+    for c in chars {
+        if c == end && !quoted {
+            return Some(s);
+        }
+        // Tag values may hold `]`, and escape quotes and backslashes
+        if end == ']' && c == '"' && !s.ends_with('\\') {
+            quoted = !quoted;
+        }
+        s.push(c);
+    }
+    None
+}
+
+fn parse_tag(tag: &str) -> Result<(String, String), PgnError> {
+    let tag = tag.trim();
+    let invalid = || PgnError::Tag(tag.to_string());
+    let space = tag.find(char::is_whitespace).ok_or_else(invalid)?;
+    let (name, value) = tag.split_at(space);
+    let value = value.trim();
+    if name.is_empty() || !value.starts_with('"') || !value.ends_with('"') || value.len() < 2 {
+        return Err(invalid());
+    }
+    let value = value[1..value.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\");
+    Ok((name.to_string(), value))
+}
+
+/// `pgn <file>...`, replays every game and shows how our evaluation moves along
+pub fn run(args: &[String]) {
+    if args.is_empty() {
+        println!("Usage: pgn <file>...");
+        return;
+    }
+    for path in args {
+        let games = match fs::read_to_string(path).map_err(|err| err.to_string()).and_then(|text| read(&text).map_err(|err| err.to_string())) {
+            Ok(games) => games,
+            Err(err) => {
+                println!("Can't read {}: {}", path, err);
+                continue;
+            }
+        };
+        for game in games {
+            println!("{} - {} {}", game.tag("White").unwrap_or("?"), game.tag("Black").unwrap_or("?"), game.result);
+            let tables = game.tables();
+            let mut pawns = PawnTable::new();
+            for (ply, m) in game.moves.iter().enumerate() {
+                let (before, after) = (&tables[ply], &tables[ply + 1]);
+                let number = if before.turn == PlayerColor::White {
+                    format!("{}.", before.fullmove_number)
+                } else {
+                    format!("{}...", before.fullmove_number)
+                };
+                // From white's side, and how much the move changed it for whoever made it
+                let mut white = |table: &Table| {
+                    let score = table.evaluate(&mut pawns);
+                    if table.turn == PlayerColor::White { score } else { -score }
+                };
+                let mut swing = white(after) - white(before);
+                if before.turn == PlayerColor::Black {
+                    swing = -swing;
+                }
+                println!("{:>6} {:<8} {:>+7.2} {:>+7.2}", number, before.san(*m), white(after) as f64 / 100.0, swing as f64 / 100.0);
+            }
+            println!();
+        }
+    }
+}
+
+impl Display for Pgn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        if !self.tags.is_empty() {
+            writeln!(f)?;
+        }
+
+        let mut tokens = Vec::new();
+        let mut table = self.initial.clone();
+        // Black's move needs its number too when it starts the game or follows a comment
+        let mut numbered = false;
+        for (ply, m) in self.moves.iter().enumerate() {
+            if table.turn == PlayerColor::White {
+                tokens.push(format!("{}.", table.fullmove_number));
+            } else if !numbered {
+                tokens.push(format!("{}...", table.fullmove_number));
+            }
+            tokens.push(table.san(*m));
+            numbered = true;
+            if let Some(Some(comment)) = self.comments.get(ply) {
+                tokens.push(format!("{{ {} }}", comment.replace('}', "")));
+                numbered = false;
+            }
+            table.process_move(*m);
+        }
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &token;
+        }
+        writeln!(f, "{}", line)
+    }
+}
+
+/// `YYYY.MM.DD` of a unix time in milliseconds, in UTC
+pub fn date(millis: u64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let days = (millis / 86_400_000) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// `[%clk h:mm:ss]` of a clock in milliseconds
+pub fn clock(millis: i64) -> String {
+    let seconds = millis.max(0) / 1000;
+    format!("[%clk {}:{:02}:{:02}]", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// `[%eval 0.35]` of an evaluation in centipawns from white's side, `[%eval #-3]` for mates
+pub fn eval(score: i32) -> String {
+    if score.abs() >= MATE_BOUND {
+        format!("[%eval #{}]", if score > 0 { (MATE - score + 1) / 2 } else { -(MATE + score) / 2 })
+    } else {
+        format!("[%eval {:.2}]", score as f64 / 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clock, date, eval, read, Pgn, PgnError, LINE_LENGTH};
+    use crate::search::MATE;
+    use crate::{Move, Table};
+
+    fn moves(game: &Pgn) -> String {
+        game.moves.iter().map(|m| m.to_string()).collect::<Vec<String>>().join(" ")
+    }
+
+    #[test]
+    fn variations_and_annotations() {
+        let games = read("1. e4 (1. d4 d5 (1... Nf6 2. c4)) 1... e5 $1 2. Nf3!? Nc6?! 3. Bb5!! $14 a6 *").unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(moves(&games[0]), "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6");
+        assert_eq!(games[0].result, "*");
+    }
+
+    #[test]
+    fn comments_and_escapes() {
+        let text = "% written by some program\n1. e4 ; the king's pawn\n1... e5 {a comment\n over lines} 2. Nf3 1-0";
+        let games = read(text).unwrap();
+        assert_eq!(moves(&games[0]), "e2e4 e7e5 g1f3");
+        assert_eq!(games[0].comments[0].as_deref(), Some("the king's pawn"));
+        assert_eq!(games[0].comments[1].as_deref(), Some("a comment over lines"));
+        assert_eq!(games[0].comments[2], None);
+        assert_eq!(games[0].result, "1-0");
+    }
+
+    #[test]
+    fn several_games() {
+        let text = "[White \"A\"]\n[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n\n[White \"B\"]\n\n1. d4 1/2-1/2\n\n[White \"C\"]\n[Result \"1-0\"]\n\n1. e4\n";
+        let games = read(text).unwrap();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games.iter().map(|g| g.tag("White").unwrap()).collect::<Vec<&str>>(), ["A", "B", "C"]);
+        assert_eq!(moves(&games[0]), "f2f3 e7e5 g2g4 d8h4");
+        assert_eq!(games[1].result, "1/2-1/2");
+        // Without a result token the tag tells how it ended
+        assert_eq!(games[2].result, "1-0");
+    }
+
+    #[test]
+    fn fen_tag_and_castling() {
+        let text = "[SetUp \"1\"]\n[FEN \"r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 20\"]\n\n20... 0-0-0 21. O-O Kb8 *";
+        let games = read(text).unwrap();
+        assert_eq!(moves(&games[0]), "e8c8 e1g1 c8b8");
+        let text = "[FEN \"r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1\"]\n\n1. 0-0 0-0-0 *";
+        assert_eq!(moves(&read(text).unwrap()[0]), "e1g1 e8c8");
+        assert!(matches!(read("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n\n*"), Err(PgnError::Fen(_))));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(read("1. e4 e4 *"), Err(PgnError::IllegalMove(_))));
+        assert!(matches!(read("1. e4 (1. d4 *"), Err(PgnError::Unclosed('('))));
+        assert!(matches!(read("1. e4 ) *"), Err(PgnError::Unopened(')'))));
+        assert!(matches!(read("1. e4 {never closed"), Err(PgnError::Unclosed('{'))));
+        assert!(matches!(read("[White \"A\"\n"), Err(PgnError::Unclosed('['))));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970.01.01");
+        assert_eq!(date(951_782_400_000), "2000.02.29");
+        // The last millisecond of the leap day, then the day after
+        assert_eq!(date(951_782_400_000 + 86_399_999), "2000.02.29");
+        assert_eq!(date(951_868_800_000), "2000.03.01");
+        // 2100 isn't a leap year
+        assert_eq!(date(4_107_456_000_000 + 86_400_000), "2100.03.01");
+        assert_eq!(date(1_735_603_200_000), "2024.12.31");
+    }
+
+    #[test]
+    fn clocks_and_evals() {
+        assert_eq!(clock(3_723_000), "[%clk 1:02:03]");
+        assert_eq!(clock(59_999), "[%clk 0:00:59]");
+        assert_eq!(clock(-5), "[%clk 0:00:00]");
+        assert_eq!(eval(35), "[%eval 0.35]");
+        assert_eq!(eval(-120), "[%eval -1.20]");
+        assert_eq!(eval(0), "[%eval 0.00]");
+        // Plies to mate become moves, counting the mating one
+        assert_eq!(eval(MATE - 1), "[%eval #1]");
+        assert_eq!(eval(MATE - 3), "[%eval #2]");
+        assert_eq!(eval(-(MATE - 2)), "[%eval #-1]");
+        assert_eq!(eval(-(MATE - 4)), "[%eval #-2]");
+    }
+
+    fn game(fen: &str, moves: &str) -> Pgn {
+        let mut pgn = Pgn::new();
+        pgn.initial = Table::from_fen(fen).unwrap();
+        pgn.moves = moves.split_whitespace().map(Move::from_str).collect();
+        pgn.comments = vec![None; pgn.moves.len()];
+        pgn
+    }
+
+    #[test]
+    fn move_numbers() {
+        let mut pgn = game("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "e2e4 e7e5 g1f3 b8c6");
+        pgn.tags.push(("Event".to_string(), "A \"quoted\" \\ name".to_string()));
+        pgn.comments[0] = Some(format!("{} {}", eval(30), clock(60_000)));
+        pgn.comments[1] = Some("a } brace".to_string());
+        pgn.result = "1-0".to_string();
+        assert_eq!(pgn.to_string(), "[Event \"A \\\"quoted\\\" \\\\ name\"]\n\n\
+            1. e4 { [%eval 0.30] [%clk 0:01:00] } 1... e5 { a  brace } 2. Nf3 Nc6 1-0\n");
+        // Black moving first gets its number too
+        let pgn = game("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 20", "e8c8 e1g1");
+        assert_eq!(pgn.to_string(), "20... O-O-O 21. O-O *\n");
+    }
+
+    #[test]
+    fn line_wrapping_and_round_trip() {
+        let moves = "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6 b5a4 g8f6 e1g1 f8e7 f1e1 b7b5 a4b3 d7d6 c2c3 e8g8 h2h3 c6b8 d2d4 b8d7 \
+            b1d2 c8b7 b3c2 f8e8 d2f1 e7f8 f1g3 g7g6 c1g5 h7h6 g5d2 f8g7 a2a4 c7c5 d4d5 c5c4 b2b4 d7b6 a4a5 b6d7";
+        let mut pgn = game("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", moves);
+        pgn.tags.push(("White".to_string(), "A".to_string()));
+        pgn.tags.push(("Black".to_string(), "B".to_string()));
+        for ply in (0..pgn.moves.len()).step_by(7) {
+            pgn.comments[ply] = Some(format!("{} {}", eval(ply as i32 * 13 - 40), clock(ply as i64 * 61_000)));
+        }
+        pgn.result = "1/2-1/2".to_string();
+
+        let text = pgn.to_string();
+        assert!(text.lines().all(|line| line.len() <= LINE_LENGTH), "{}", text);
+        assert!(text.lines().filter(|line| !line.starts_with('[')).count() > 3, "{}", text);
+        let games = read(&text).unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].tags, pgn.tags);
+        assert_eq!(games[0].moves, pgn.moves);
+        assert_eq!(games[0].comments, pgn.comments);
+        assert_eq!(games[0].result, pgn.result);
+        assert_eq!(games[0].to_string(), text);
+    }
+}
@@ -0,0 +1,197 @@
+//! Standard Algebraic Notation, the way people write moves: `Nf3`, `exd5`, `O-O`, `e8=Q#`
+
+use crate::{Move, Piece, Position, Table};
+
+impl Table {
+    /// `m` in SAN, it has to be a legal move of the side to move
+    pub fn san(&self, m: Move) -> String {
+        let piece = self.get_piece_at(m.a);
+        let mut san = if piece.value() == Piece::WhiteKing.value() && (m.b.0 - m.a.0).abs() == 2 {
+            if m.b.0 > m.a.0 { "O-O".to_string() } else { "O-O-O".to_string() }
+        } else if piece.value() == 1 {
+            let mut san = String::new();
+            if m.a.0 != m.b.0 {
+                san += &m.a.to_string()[..1];
+                san += "x";
+            }
+            san += &m.b.to_string();
+            if let Some(p) = m.promotion {
+                san += "=";
+                san += &p.to_string();
+            }
+            san
+        } else {
+            let mut san = piece.to_string();
+            // Other pieces of the same kind going to the same square
+            let others: Vec<Position> = self.legal_moves().iter()
+                .filter(|o| o.b == m.b && o.a != m.a && self.get_piece_at(o.a) == piece)
+                .map(|o| o.a)
+                .collect();
+            if !others.is_empty() {
+                if others.iter().all(|p| p.0 != m.a.0) {
+                    san += &m.a.to_string()[..1];
+                } else if others.iter().all(|p| p.1 != m.a.1) {
+                    san += &m.a.to_string()[1..];
+                } else {
+                    san += &m.a.to_string();
+                }
+            }
+            if self.get_piece_at(m.b) != Piece::None {
+                san += "x";
+            }
+            san += &m.b.to_string();
+            san
+        };
+        let table = self.assume_move(m);
+        if table.in_check(table.turn) {
+            san += if table.legal_moves().is_empty() { "#" } else { "+" };
+        }
+        san
+    }
+
+    /// The legal move written as `san`, check marks and annotations like `!?` may be left out
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let san = san.trim_end_matches(|c| "+#!?".contains(c));
+        let king = Piece::WhiteKing.with_color(self.turn);
+        let castle_file = match san {
+            "O-O" | "0-0" => Some(7),
+            "O-O-O" | "0-0-0" => Some(3),
+            _ => None
+        };
+        if let Some(file) = castle_file {
+            return self.legal_moves().iter()
+                .find(|m| self.get_piece_at(m.a) == king && m.b.0 == file && (m.b.0 - m.a.0).abs() == 2)
+                .copied();
+        }
+
+        let mut chars: Vec<char> = san.chars().filter(|c| *c != 'x' && *c != '-' && *c != ':').collect();
+        let piece = match chars.first() {
+            Some(c) if "KQRBN".contains(*c) => {
+                let piece = Piece::from_char(*c, self.turn);
+                chars.remove(0);
+                piece?
+            }
+            _ => Piece::WhitePawn.with_color(self.turn)
+        };
+        // `e8=Q` or `e8Q`
+        let promotion = match chars.last() {
+            Some(c) if "QRBN".contains(*c) => {
+                let promotion = Piece::from_char(*c, self.turn);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+                Some(promotion?)
+            }
+            _ => None
+        };
+        if chars.len() < 2 {
+            return None;
+        }
+        let to: String = chars[chars.len() - 2..].iter().collect();
+        let to = Position::parse(&to)?;
+        // Whatever is left tells apart pieces going to the same square
+        let from = &chars[..chars.len() - 2];
+        let file = from.iter().find(|c| c.is_ascii_lowercase()).map(|c| *c as i64 - 'a' as i64 + 1);
+        let rank = from.iter().find(|c| c.is_ascii_digit()).map(|c| *c as i64 - '0' as i64);
+
+        let mut found = None;
+        for m in self.legal_moves().iter() {
+            if self.get_piece_at(m.a) == piece && m.b == to && m.promotion == promotion
+                && (file.is_none() || file == Some(m.a.0)) && (rank.is_none() || rank == Some(m.a.1)) {
+                if found.is_some() {
+                    // Ambiguous
+                    return None;
+                }
+                found = Some(*m);
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Move, Table};
+
+    fn san(fen: &str, uci: &str) -> String {
+        Table::from_fen(fen).unwrap().san(Move::from_str(uci))
+    }
+
+    fn parse(fen: &str, san: &str) -> Option<String> {
+        Table::from_fen(fen).unwrap().parse_san(san).map(|m| m.to_string())
+    }
+
+    #[test]
+    fn disambiguation() {
+        let by_file = "4k3/8/8/8/8/8/4K3/R6R w - - 0 1";
+        assert_eq!(san(by_file, "a1d1"), "Rad1");
+        assert_eq!(san(by_file, "h1d1"), "Rhd1");
+        let by_rank = "4k3/8/8/R7/8/8/4K3/R7 w - - 0 1";
+        assert_eq!(san(by_rank, "a1a3"), "R1a3");
+        assert_eq!(san(by_rank, "a5a3"), "R5a3");
+        let by_square = "4k3/8/8/8/8/Q7/4K3/Q1Q5 w - - 0 1";
+        assert_eq!(san(by_square, "a1b2"), "Qa1b2");
+        assert_eq!(san(by_square, "a3b2"), "Q3b2");
+        assert_eq!(san(by_square, "c1b2"), "Qcb2");
+    }
+
+    #[test]
+    fn promotion() {
+        let fen = "r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "b7b8q"), "b8=Q+");
+        assert_eq!(san(fen, "b7b8n"), "b8=N");
+        assert_eq!(san(fen, "b7a8q"), "bxa8=Q+");
+        assert_eq!(parse(fen, "bxa8=Q+").as_deref(), Some("b7a8q"));
+        assert_eq!(parse(fen, "b8N").as_deref(), Some("b7b8n"));
+        // A pawn reaching the last rank has to say what it becomes
+        assert_eq!(parse(fen, "b8"), None);
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+        assert_eq!(parse(fen, "O-O").as_deref(), Some("e1g1"));
+        assert_eq!(parse(fen, "0-0-0").as_deref(), Some("e1c1"));
+        let black = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1";
+        assert_eq!(parse(black, "O-O-O").as_deref(), Some("e8c8"));
+    }
+
+    #[test]
+    fn check_and_mate() {
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        assert_eq!(san(fen, "f3f7"), "Qxf7#");
+        assert_eq!(san(fen, "c4f7"), "Bxf7+");
+        assert_eq!(parse(fen, "Qxf7#").as_deref(), Some("f3f7"));
+        assert_eq!(parse(fen, "Qxf7").as_deref(), Some("f3f7"));
+        assert_eq!(parse(fen, "Bxf7+!?").as_deref(), Some("c4f7"));
+    }
+
+    #[test]
+    fn rejected() {
+        assert_eq!(parse("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "Rd1"), None);
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(parse(start, "Nf6"), None);
+        assert_eq!(parse(start, "e5"), None);
+        assert_eq!(parse(start, "O-O"), None);
+        assert_eq!(parse(start, "Zz9"), None);
+        // The knight is pinned to the king
+        assert_eq!(parse("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1", "Nc3"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "4k3/8/8/8/8/Q7/4K3/Q1Q5 w - - 0 1",
+        ].iter() {
+            let table = Table::from_fen(fen).unwrap();
+            for m in table.legal_moves().iter() {
+                assert_eq!(table.parse_san(&table.san(*m)), Some(*m));
+            }
+        }
+    }
+}
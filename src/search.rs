@@ -0,0 +1,437 @@
+//! Looking ahead: negamax alpha-beta over the legal moves, deepened one ply at a time
+//! so every finished iteration leaves a best line to fall back on, with a quiescence
+//! search at the leaves so exchanges are played out before a position is evaluated.
+//! Helper threads can search the same position alongside, sharing the transposition table
+
+use crate::config::Pruning;
+use crate::movepick::{History, Killers, MovePicker};
+use crate::pawns::{BorrowedPawnTable, PawnTables};
+use crate::time::{Budget, Deadline};
+use crate::tt::{Bound, Entry, TranspositionTable};
+use crate::{Move, Table};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// Score of mating on the board, mating in `n` plies scores `MATE - n`
+pub const MATE: i32 = 30_000;
+/// Scores this close to `MATE` are forced mates
+pub const MATE_BOUND: i32 = MATE - 1000;
+const INFINITY: i32 = MATE + 1;
+/// Checks stop being extended this deep
+const MAX_PLY: usize = 64;
+/// Centipawns a quiet move is assumed to gain at most, per ply of depth left
+const FUTILITY_MARGIN: i32 = 200;
+const REVERSE_FUTILITY_MARGIN: i32 = 120;
+
+/// Best line found by the last finished iteration
+pub struct SearchResult {
+    /// From the side to move, in centipawns unless it's a mate score
+    pub score: i32,
+    /// Principal variation, the moves both sides are expected to play
+    pub pv: Vec<Move>,
+}
+
+pub struct Search {
+    /// Hashes of the positions before the one on the board, the game's and then the search's own
+    hashes: Vec<u64>,
+    /// Best line of the previous iteration, tried first in the next
+    pv: Vec<Move>,
+    nodes: u64,
+    tt: Arc<TranspositionTable>,
+    killers: Killers,
+    history: History,
+    pawns: BorrowedPawnTable,
+    /// Shared by the threads of a search, only the first one stops on it
+    deadline: Arc<Deadline>,
+    /// Set when the hard limit passed, everything searched after that is thrown away
+    stopped: bool,
+    /// Shared by the threads of a search, set when they all have to stop
+    stop: Arc<AtomicBool>,
+    /// 0 for the thread that reports and whose result counts, helpers count up from 1
+    thread: usize,
+    pruning: Pruning,
+    /// The node about to be searched follows a null move, two in a row prove nothing
+    after_null: bool,
+}
+
+impl Search {
+    /// `history` holds the hashes of the positions the game went through before this one,
+    /// `pawns` are the game's pawn tables, the search borrows one for each thread
+    pub fn new(history: &[u64], tt: Arc<TranspositionTable>, pawns: &PawnTables, pruning: Pruning) -> Search {
+        tt.new_search();
+        Search {
+            hashes: history.to_vec(),
+            pv: Vec::new(),
+            nodes: 0,
+            tt,
+            killers: Killers::new(),
+            history: History::new(),
+            pawns: pawns.take(),
+            deadline: Arc::new(Deadline::new()),
+            stopped: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: 0,
+            pruning,
+            after_null: false,
+        }
+    }
+
+    /// Another thread of the same search, with killers, history and pawn table of its own
+    fn helper(&self, thread: usize) -> Search {
+        Search {
+            hashes: self.hashes.clone(),
+            pv: Vec::new(),
+            nodes: 0,
+            tt: self.tt.clone(),
+            killers: Killers::new(),
+            history: History::new(),
+            pawns: self.pawns.tables().take(),
+            deadline: self.deadline.clone(),
+            stopped: false,
+            stop: self.stop.clone(),
+            thread,
+            pruning: self.pruning,
+            after_null: false,
+        }
+    }
+
+    /// Searches `table` one ply deeper each iteration up to `depth` plies or until the
+    /// budget runs out, without one until stopped by a `Ponder` being dropped or given one.
+    /// The first iteration is always finished. With more than one of
+    /// `threads`, helpers search the same position until this one is done (Lazy SMP),
+    /// what they find reaches it only through the transposition table
+    pub fn run(&mut self, table: &Table, depth: u32, budget: Option<Budget>, threads: usize) -> SearchResult {
+        if let Some(budget) = budget {
+            self.deadline.set(budget);
+        }
+        let history = self.hashes.clone();
+        // Moves at the root are few, so the heuristic can afford to order them
+        let mut root: Vec<(i32, Move)> = table.legal_moves().iter().map(|m| (table.move_score(*m, &history), *m)).collect();
+        root.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        let root: Vec<Move> = root.into_iter().map(|(_, m)| m).collect();
+
+        thread::scope(|scope| {
+            for thread in 1..threads {
+                let mut helper = self.helper(thread);
+                let root = &root;
+                scope.spawn(move || helper.iterate(table, root, depth));
+            }
+            let result = self.iterate(table, &root, depth);
+            self.stop.store(true, Relaxed);
+            result
+        })
+    }
+
+    fn iterate(&mut self, table: &Table, root: &[Move], depth: u32) -> SearchResult {
+        let t = Instant::now();
+        // Half the helpers start a ply deeper, so the threads aren't all at the same depth
+        let first = 1 + self.thread as u32 % 2;
+        let mut result = SearchResult { score: 0, pv: Vec::new() };
+        for d in first..=depth.max(1) {
+            if d > first && self.thread == 0 && self.deadline.soft_passed() {
+                break;
+            }
+            let mut pv = Vec::new();
+            let score = self.root(table, root, d, &mut pv);
+            if self.stopped {
+                break;
+            }
+            self.pv = pv.clone();
+            result = SearchResult { score, pv };
+            if self.thread == 0 {
+                println!("Depth {} score {} nodes {} time {:.3}s pv {}", d, score_string(score), self.nodes, t.elapsed().as_secs_f64(), self.pv_string(table));
+            }
+            // Nothing to find past a forced mate
+            if score.abs() >= MATE_BOUND {
+                break;
+            }
+        }
+        result
+    }
+
+    fn root(&mut self, table: &Table, moves: &[Move], depth: u32, pv: &mut Vec<Move>) -> i32 {
+        let mut moves = moves.to_vec();
+        // What the last search expected here may still be best
+        let best = self.pv.first().copied().or_else(|| self.tt.probe(table.hash(), 0).and_then(|e| e.m));
+        if let Some(best) = best {
+            if let Some(i) = moves.iter().position(|m| *m == best) {
+                let best = moves.remove(i);
+                moves.insert(0, best);
+            }
+        }
+        if moves.is_empty() {
+            return if table.in_check(table.turn) { -MATE } else { 0 };
+        }
+        let mut alpha = -INFINITY;
+        for m in moves {
+            let mut line = Vec::new();
+            self.hashes.push(table.hash());
+            let score = -self.negamax(&table.assume_move(m), depth - 1, 1, -INFINITY, -alpha, &mut line);
+            self.hashes.pop();
+            if self.stopped {
+                return alpha;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(m);
+                pv.extend(line);
+            }
+        }
+        self.tt.store(table.hash(), 0, Entry { m: pv.first().copied(), score: alpha, depth, bound: Bound::Exact });
+        alpha
+    }
+
+    fn negamax(&mut self, table: &Table, mut depth: u32, ply: usize, mut alpha: i32, beta: i32, pv: &mut Vec<Move>) -> i32 {
+        let after_null = std::mem::replace(&mut self.after_null, false);
+        if self.check_time() {
+            return 0;
+        }
+        if self.is_draw(table) {
+            return 0;
+        }
+        let in_check = table.in_check(table.turn);
+        // A check is looked at a ply deeper, so it isn't hidden behind the horizon
+        if in_check && self.pruning.check_extensions && ply < MAX_PLY {
+            depth += 1;
+        }
+        if depth == 0 {
+            return self.quiescence(table, ply, alpha, beta);
+        }
+
+        let entry = self.tt.probe(table.hash(), ply);
+        let tt_move = entry.as_ref().and_then(|e| e.m);
+        if let Some(entry) = entry.filter(|e| e.depth >= depth) {
+            let cut = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            if cut {
+                pv.clear();
+                pv.extend(tt_move);
+                return entry.score;
+            }
+        }
+
+        // Nodes with a null window only have to prove the score is on one side of it
+        let null_window = beta - alpha == 1;
+        let static_eval = table.evaluate(&mut self.pawns);
+        let selective = null_window && !in_check && beta.abs() < MATE_BOUND;
+
+        // Reverse futility: so far above beta a few quiet plies won't bring it back down
+        if self.pruning.reverse_futility && selective && depth <= 3 && static_eval - REVERSE_FUTILITY_MARGIN * depth as i32 >= beta {
+            return static_eval;
+        }
+
+        // Null move: if passing still keeps the score above beta, a real move surely does. Not
+        // with pawns and king alone, where having to move may be all that loses (zugzwang)
+        if self.pruning.null_move && selective && !after_null && depth >= 3 && static_eval >= beta && table.has_pieces(table.turn) {
+            let reduction = if depth > 6 { 3 } else { 2 };
+            self.hashes.push(table.hash());
+            self.after_null = true;
+            let score = -self.negamax(&table.null_move(), depth - 1 - reduction, ply + 1, -beta, -beta + 1, &mut Vec::new());
+            self.hashes.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                // A mate found after passing isn't proven
+                return if score >= MATE_BOUND { beta } else { score };
+            }
+        }
+
+        // Futility: close to the leaves, a quiet move can't make up for being far below alpha
+        let futile = self.pruning.futility && selective && depth <= 2 && static_eval + FUTILITY_MARGIN * depth as i32 <= alpha;
+
+        let best_move = tt_move.or_else(|| self.pv.get(ply).copied());
+        let mut moves = MovePicker::new(table, best_move, self.killers.get(ply));
+        let original_alpha = alpha;
+        let mut legal = 0;
+        let mut searched = 0;
+        while let Some(m) = moves.next(&self.history) {
+            legal += 1;
+            let quiet = !table.is_capture(&m) && m.promotion.is_none();
+            let child = table.assume_move(m);
+            let gives_check = child.in_check(child.turn);
+            if futile && quiet && !gives_check && searched > 0 {
+                continue;
+            }
+
+            let mut line = Vec::new();
+            self.hashes.push(table.hash());
+            // The first move gets the full window, the rest only have to show they're worse
+            // and are searched again if not. Late quiet moves are searched less deep first
+            let score = if searched == 0 {
+                -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha, &mut line)
+            } else {
+                let reduction = if self.pruning.late_move_reductions && depth >= 3 && searched >= 3 && quiet && !in_check && !gives_check {
+                    if searched >= 8 { 2 } else { 1 }
+                } else {
+                    0
+                };
+                let mut score = -self.negamax(&child, depth - 1 - reduction.min(depth - 1), ply + 1, -alpha - 1, -alpha, &mut line);
+                if score > alpha && reduction > 0 {
+                    score = -self.negamax(&child, depth - 1, ply + 1, -alpha - 1, -alpha, &mut line);
+                }
+                if score > alpha && score < beta {
+                    score = -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha, &mut line);
+                }
+                score
+            };
+            self.hashes.pop();
+            searched += 1;
+            if self.stopped {
+                return 0;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(m);
+                pv.extend(line);
+                if alpha >= beta {
+                    if quiet {
+                        self.killers.store(ply, m);
+                        self.history.reward(table, m, depth);
+                    }
+                    break;
+                }
+            }
+        }
+        if legal == 0 {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+        let bound = if alpha >= beta {
+            Bound::Lower
+        } else if alpha > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        let m = if bound == Bound::Upper { None } else { pv.first().copied() };
+        self.tt.store(table.hash(), ply, Entry { m, score: alpha, depth, bound });
+        alpha
+    }
+
+    /// Searches only captures and promotions until the position is quiet. The side to move
+    /// may stand pat, keeping the evaluation as it is instead of taking, unless in check
+    /// where every evasion is searched
+    fn quiescence(&mut self, table: &Table, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.check_time() {
+            return 0;
+        }
+        let in_check = table.in_check(table.turn);
+        // Captures losing material can't raise the score above standing pat, they're left out
+        let mut moves = if in_check {
+            MovePicker::new(table, None, [None, None])
+        } else {
+            let stand_pat = table.evaluate(&mut self.pawns);
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+            MovePicker::captures(table)
+        };
+
+        let mut searched = 0;
+        while let Some(m) = moves.next(&self.history) {
+            searched += 1;
+            let score = -self.quiescence(&table.assume_move(m), ply + 1, -beta, -alpha);
+            if self.stopped {
+                return 0;
+            }
+            if score > alpha {
+                alpha = score;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        if in_check && searched == 0 {
+            return -MATE + ply as i32;
+        }
+        alpha
+    }
+
+    /// Counts a node and tells whether the search has to stop, the clock is only looked
+    /// at every few thousand nodes and never before the first iteration is done
+    fn check_time(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes & 2047 == 0 {
+            self.stopped |= self.stop.load(Relaxed);
+            if self.thread == 0 && !self.pv.is_empty() {
+                self.stopped |= self.deadline.hard_passed();
+            }
+        }
+        self.stopped
+    }
+
+    /// Drawn by the rules, a single repetition is enough as the players could repeat again
+    fn is_draw(&self, table: &Table) -> bool {
+        table.halfmove_clock >= 100
+            || table.repetitions(&self.hashes) > 0
+            || table.occupied().count_ones() <= 4 && table.insufficient_material()
+    }
+
+    fn pv_string(&self, table: &Table) -> String {
+        let mut table = table.clone();
+        let mut line = Vec::new();
+        for m in &self.pv {
+            line.push(table.san(*m));
+            table.process_move(*m);
+        }
+        line.join(" ")
+    }
+}
+
+/// A search of the position after the move the opponent is expected to play, running in
+/// the background while they think about it. Dropping it stops the search
+pub struct Ponder {
+    /// Of the position searched
+    pub hash: u64,
+    stop: Arc<AtomicBool>,
+    deadline: Arc<Deadline>,
+    thread: Option<JoinHandle<SearchResult>>,
+}
+
+impl Ponder {
+    /// Runs `search` on `table` until it's hit or dropped
+    pub fn start(mut search: Search, table: Table, depth: u32, threads: usize) -> Ponder {
+        let stop = search.stop.clone();
+        let deadline = search.deadline.clone();
+        let hash = table.hash();
+        let thread = thread::spawn(move || search.run(&table, depth, None, threads));
+        Ponder { hash, stop, deadline, thread: Some(thread) }
+    }
+
+    /// The opponent played the expected move, the search goes on within `budget`
+    pub fn hit(mut self, budget: Budget) -> SearchResult {
+        self.deadline.set(budget);
+        match self.thread.take().map(|thread| thread.join()) {
+            Some(Ok(result)) => result,
+            _ => SearchResult { score: 0, pv: Vec::new() }
+        }
+    }
+}
+
+impl Drop for Ponder {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// `+1.25`, or `#3` and `#-2` for mates in moves
+pub fn score_string(score: i32) -> String {
+    if score >= MATE_BOUND {
+        format!("#{}", (MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        format!("#-{}", (MATE + score) / 2)
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
@@ -0,0 +1,122 @@
+//! Static exchange evaluation: what a capture wins once both sides have taken back on
+//! its square with their least valuable pieces, sliders behind others joining in as the
+//! square opens up
+
+use crate::{opposite, Move, Piece, Table};
+
+/// Attackers are taken from the cheapest up
+const ATTACKERS: [Piece; 6] = [
+    Piece::WhitePawn, Piece::WhiteKnight, Piece::WhiteBishop, Piece::WhiteRook, Piece::WhiteQueen, Piece::WhiteKing,
+];
+
+impl Table {
+    /// Material in centipawns the moving side wins by the exchange `m` starts, each side
+    /// may stop taking whenever going on would lose. Quiet moves score what the moved
+    /// piece loses standing on its new square. Pins are not taken into account
+    pub fn see(&self, m: Move) -> i32 {
+        let to = m.b.index();
+        let piece = self.get_piece_at(m.a);
+        let mut occupied = self.occupied() & !(1 << m.a.index());
+        let captured = if self.is_en_passant(&m) {
+            // The taken pawn is behind the target square
+            occupied &= !(1 << (m.a.index() / 8 * 8 + to % 8));
+            Piece::WhitePawn.value()
+        } else {
+            self.get_piece_at(m.b).value()
+        };
+
+        let mut gain = [0; 32];
+        gain[0] = captured * 100;
+        let mut on_square = piece.value();
+        if let Some(p) = m.promotion {
+            gain[0] += (p.value() - piece.value()) * 100;
+            on_square = p.value();
+        }
+
+        let mut side = opposite(piece.color());
+        let mut d = 0;
+        loop {
+            d += 1;
+            // What `side` wins taking the piece on the square, if nothing is taken back
+            gain[d] = on_square * 100 - gain[d - 1];
+            // Neither taking nor not taking changes the outcome any more
+            if (-gain[d - 1]).max(gain[d]) < 0 || d + 1 == gain.len() {
+                break;
+            }
+            let attackers = self.attackers(to, side, occupied) & occupied;
+            let attacker = ATTACKERS.iter().map(|p| p.with_color(side)).find(|p| attackers & self.bitboard(*p) != 0);
+            let attacker = match attacker {
+                Some(attacker) => attacker,
+                None => break
+            };
+            let from = (attackers & self.bitboard(attacker)).trailing_zeros();
+            // A king can only take on a square the other side no longer attacks
+            if attacker.value() == Piece::WhiteKing.value()
+                && self.attackers(to, opposite(side), occupied & !(1 << from)) & occupied != 0 {
+                break;
+            }
+            occupied &= !(1 << from);
+            on_square = attacker.value();
+            side = opposite(side);
+        }
+        while d > 1 {
+            d -= 1;
+            gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+        }
+        gain[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Move, Table};
+
+    fn see(fen: &str, uci: &str) -> i32 {
+        Table::from_fen(fen).unwrap().see(Move::from_str(uci))
+    }
+
+    #[test]
+    fn captures() {
+        assert_eq!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"), 100);
+        // The knight takes a pawn and is taken back, the rooks and queens behind don't help
+        assert_eq!(see("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5"), -200);
+        // A defended knight taken by a lone rook
+        assert_eq!(see("4r1k1/8/8/4n3/8/8/4R3/6K1 w - - 0 1", "e2e5"), -200);
+    }
+
+    #[test]
+    fn x_rays() {
+        // The rook or queen behind the first attacker joins in once it has taken
+        assert_eq!(see("4r1k1/8/8/4n3/8/8/4R3/4R1K1 w - - 0 1", "e2e5"), 300);
+        assert_eq!(see("4r1k1/8/8/4n3/8/8/4R3/4Q1K1 w - - 0 1", "e2e5"), 300);
+        // With a battery on both sides, taking back with the queen would lose it
+        assert_eq!(see("4q1k1/4r3/8/4n3/8/8/4R3/4Q1K1 w - - 0 1", "e2e5"), -200);
+        // A bishop behind the queen on the diagonal
+        assert_eq!(see("6k1/8/5p2/8/8/2Q5/1B6/6K1 w - - 0 1", "c3f6"), 100);
+        assert_eq!(see("6k1/6p1/5p2/8/8/2Q5/1B6/6K1 w - - 0 1", "c3f6"), -800);
+    }
+
+    #[test]
+    fn en_passant() {
+        assert_eq!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+        assert_eq!(see("4k3/2p5/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 0);
+    }
+
+    #[test]
+    fn promotions_and_quiet_moves() {
+        assert_eq!(see("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), 800);
+        assert_eq!(see("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), -100);
+        assert_eq!(see("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 1300);
+        // A knight stepping where a pawn takes it
+        assert_eq!(see("4k3/8/3p4/8/8/5N2/8/4K3 w - - 0 1", "f3e5"), -300);
+        assert_eq!(see("4k3/8/8/8/8/5N2/8/4K3 w - - 0 1", "f3e5"), 0);
+    }
+
+    #[test]
+    fn kings() {
+        // A king may only take what nothing defends
+        assert_eq!(see("4k3/8/8/8/8/3p4/4K3/8 w - - 0 1", "e2d3"), 100);
+        assert_eq!(see("4k3/8/8/8/8/3p4/8/4K3 b - - 0 1", "d3d2"), -100);
+        assert_eq!(see("4k3/8/8/8/4n3/3p4/8/4K3 b - - 0 1", "d3d2"), 0);
+    }
+}
@@ -0,0 +1,156 @@
+//! How long to think about a move, from the clocks lichess sends with every game state
+
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+/// When the search should stop
+#[derive(Copy, Clone)]
+pub struct Budget {
+    /// No new iteration is started after this
+    pub soft: Instant,
+    /// The search is cut off here, even in the middle of an iteration
+    pub hard: Instant,
+}
+
+/// The budget of a running search, can be given to it from another thread. Pondering
+/// starts without one, as the clock only starts once the opponent played the expected move
+pub struct Deadline {
+    start: Instant,
+    /// Milliseconds after `start`, `u64::MAX` while there's no budget
+    soft: AtomicU64,
+    hard: AtomicU64,
+}
+
+impl Deadline {
+    pub fn new() -> Deadline {
+        Deadline { start: Instant::now(), soft: AtomicU64::new(u64::MAX), hard: AtomicU64::new(u64::MAX) }
+    }
+
+    pub fn set(&self, budget: Budget) {
+        self.soft.store(budget.soft.saturating_duration_since(self.start).as_millis() as u64, Relaxed);
+        self.hard.store(budget.hard.saturating_duration_since(self.start).as_millis() as u64, Relaxed);
+    }
+
+    /// No new iteration should be started
+    pub fn soft_passed(&self) -> bool {
+        self.start.elapsed().as_millis() as u64 >= self.soft.load(Relaxed)
+    }
+
+    /// The search has to stop now
+    pub fn hard_passed(&self) -> bool {
+        self.start.elapsed().as_millis() as u64 >= self.hard.load(Relaxed)
+    }
+}
+
+pub struct TimeManager {
+    /// Milliseconds it takes lichess to take a move we send, averaged over the game
+    lag: f64,
+    /// The most a move may take, however much time is on the clock
+    max: Duration,
+}
+
+/// Lag assumed until the first move was sent
+const INITIAL_LAG: f64 = 200.0;
+/// Weight of the newest measurement in the lag average
+const LAG_WEIGHT: f64 = 0.2;
+/// Kept on the clock for whatever isn't measured
+const OVERHEAD: i64 = 50;
+
+impl TimeManager {
+    pub fn new(max: Duration) -> TimeManager {
+        TimeManager { lag: INITIAL_LAG, max }
+    }
+
+    /// Budget for a move with `time` left on our clock and `increment` added after it, both
+    /// in milliseconds. Spreads the time over the moves the game probably still lasts
+    pub fn budget(&self, time: i64, increment: i64, fullmove_number: u32) -> Budget {
+        let now = Instant::now();
+        let (soft, hard) = self.limits(time, increment, fullmove_number);
+        Budget { soft: now + Duration::from_millis(soft), hard: now + Duration::from_millis(hard) }
+    }
+
+    /// Milliseconds from now of the soft and hard limits of `budget`
+    fn limits(&self, time: i64, increment: i64, fullmove_number: u32) -> (u64, u64) {
+        let left = (time - self.lag as i64 * 2 - OVERHEAD).max(0);
+        let moves_to_go = (50 - fullmove_number as i64).max(20);
+        let hard = (left / 3).min((left / moves_to_go + increment * 3 / 4) * 4);
+        let soft = (left / moves_to_go + increment * 3 / 4).min(hard);
+        let max = self.max.as_millis() as i64;
+        (soft.min(max / 2) as u64, hard.min(max) as u64)
+    }
+
+    /// Budget when the game has no clock
+    pub fn unlimited(&self) -> Budget {
+        let now = Instant::now();
+        Budget { soft: now + self.max / 2, hard: now + self.max }
+    }
+
+    /// Takes in how long sending a move took
+    pub fn record_lag(&mut self, lag: Duration) {
+        self.lag = self.lag * (1.0 - LAG_WEIGHT) + lag.as_millis() as f64 * LAG_WEIGHT;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TimeManager, INITIAL_LAG, OVERHEAD};
+    use std::time::Duration;
+
+    const MAX: u64 = 60_000;
+
+    /// The limits, checked to be in order and within the most a move may take
+    fn limits(manager: &TimeManager, time: i64, increment: i64, fullmove_number: u32) -> (u64, u64) {
+        let (soft, hard) = manager.limits(time, increment, fullmove_number);
+        assert!(soft <= hard && hard <= MAX, "{} {} with {}+{} at move {}", soft, hard, time, increment, fullmove_number);
+        (soft, hard)
+    }
+
+    #[test]
+    fn clocks() {
+        let manager = TimeManager::new(Duration::from_millis(MAX));
+        for time in [0, 500, 1_000, 3_000, 10_000, 60_000, 180_000, 600_000, 3_600_000, 10_800_000].iter() {
+            for increment in [0, 1_000, 2_000, 30_000].iter() {
+                for fullmove_number in [1, 20, 40, 80, 200].iter() {
+                    let (_, hard) = limits(&manager, *time, *increment, *fullmove_number);
+                    // Never more than a third of the clock, the increment only comes after the move
+                    assert!(hard as i64 <= *time / 3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn low_clock() {
+        let manager = TimeManager::new(Duration::from_millis(MAX));
+        // Whatever lag and overhead take is never spent thinking
+        let reserved = INITIAL_LAG as i64 * 2 + OVERHEAD;
+        for time in [-1_000, 0, reserved / 2, reserved].iter() {
+            assert_eq!(limits(&manager, *time, 0, 30), (0, 0));
+            assert_eq!(limits(&manager, *time, 5_000, 30), (0, 0));
+        }
+        let (soft, hard) = limits(&manager, 1_000, 0, 1);
+        assert!(soft > 0 && hard < 200, "{} {}", soft, hard);
+        // A slow connection leaves less time
+        let mut slow = TimeManager::new(Duration::from_millis(MAX));
+        for _ in 0..20 {
+            slow.record_lag(Duration::from_millis(400));
+        }
+        assert!(limits(&slow, 1_000, 0, 1).1 < hard);
+        assert_eq!(limits(&slow, 800, 0, 1), (0, 0));
+    }
+
+    #[test]
+    fn capped() {
+        let manager = TimeManager::new(Duration::from_millis(MAX));
+        assert_eq!(limits(&manager, 10_800_000, 60_000, 40), (MAX / 2, MAX));
+        let manager = TimeManager::new(Duration::from_millis(1_000));
+        assert_eq!(manager.limits(3_600_000, 0, 40), (500, 1_000));
+    }
+
+    #[test]
+    fn unlimited() {
+        let manager = TimeManager::new(Duration::from_millis(MAX));
+        let budget = manager.unlimited();
+        assert_eq!(budget.hard - budget.soft, Duration::from_millis(MAX / 2));
+    }
+}
@@ -0,0 +1,239 @@
+//! Transposition table: what the search found out about positions, by their hash, so a
+//! position reached again by another move order, or on a later move, isn't searched twice.
+//! Shared by all threads searching a game without locking, see `Slot`
+
+use crate::search::MATE_BOUND;
+use crate::{Move, Piece, Position};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering::Relaxed};
+
+/// How the stored score relates to the real one
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    /// The real score is at least this, a move was good enough to cut the search
+    Lower,
+    /// The real score is at most this, no move reached alpha
+    Upper,
+}
+
+pub struct Entry {
+    /// Best move, or the one that cut the search
+    pub m: Option<Move>,
+    pub score: i32,
+    pub depth: u32,
+    pub bound: Bound,
+}
+
+/// An entry packed in a `u64`: the move in bits 0-15, the score in 16-31, the depth in
+/// 32-39, the bound in 40-41 and the age of the search in 42-49. The hash is stored xored
+/// with it, so when two threads write the slot at once and its halves end up from
+/// different entries, the hash doesn't match any more and the slot reads as empty
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    /// Goes up with every search, entries of older ones are the first to be replaced
+    age: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// A table taking about `mb` megabytes
+    pub fn new(mb: usize) -> TranspositionTable {
+        let len = (mb * 1024 * 1024 / std::mem::size_of::<Slot>()).max(1);
+        TranspositionTable {
+            slots: (0..len).map(|_| Slot::default()).collect(),
+            age: AtomicU8::new(0),
+        }
+    }
+
+    /// To be called before every search, not by the helper threads joining it
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Relaxed);
+    }
+
+    /// The hash and entry in a slot
+    fn load(&self, index: usize) -> (u64, u64) {
+        let slot = &self.slots[index];
+        let data = slot.data.load(Relaxed);
+        (slot.key.load(Relaxed) ^ data, data)
+    }
+
+    fn index(&self, hash: u64) -> usize {
+        ((hash as u128 * self.slots.len() as u128) >> 64) as usize
+    }
+
+    /// What is known about the position with `hash`, `ply` plies from the root
+    pub fn probe(&self, hash: u64, ply: usize) -> Option<Entry> {
+        let (key, data) = self.load(self.index(hash));
+        if key != hash || data == 0 {
+            return None;
+        }
+        Some(Entry {
+            m: unpack_move(data & 0xFFFF),
+            score: from_tt(((data >> 16) & 0xFFFF) as u16 as i16 as i32, ply),
+            depth: ((data >> 32) & 0xFF) as u32,
+            bound: match (data >> 40) & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+        })
+    }
+
+    /// Keeps the entry unless the slot holds a deeper one of the same search about another position
+    pub fn store(&self, hash: u64, ply: usize, entry: Entry) {
+        let index = self.index(hash);
+        let (key, data) = self.load(index);
+        let age = self.age.load(Relaxed);
+        let old_depth = ((data >> 32) & 0xFF) as u32;
+        let old_age = ((data >> 42) & 0xFF) as u8;
+        if key != hash && old_age == age && old_depth > entry.depth {
+            return;
+        }
+        // A cut move is still worth trying first, keep the one there was
+        let m = match entry.m {
+            Some(m) => pack_move(m),
+            None if key == hash => data & 0xFFFF,
+            None => 0,
+        };
+        let bound = match entry.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let data = m
+            | (to_tt(entry.score, ply) as i16 as u16 as u64) << 16
+            | (entry.depth.min(255) as u64) << 32
+            | bound << 40
+            | (age as u64) << 42;
+        self.slots[index].key.store(hash ^ data, Relaxed);
+        self.slots[index].data.store(data, Relaxed);
+    }
+}
+
+/// Mate scores count plies from the root, stored they count from the position itself
+fn to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// From square in bits 0-5, to square in 6-11 and the promotion in 12-14
+fn pack_move(m: Move) -> u64 {
+    let promotion = match m.promotion {
+        None => 0,
+        Some(p) => match p {
+            Piece::WhiteQueen | Piece::BlackQueen => 1,
+            Piece::WhiteRook | Piece::BlackRook => 2,
+            Piece::WhiteBishop | Piece::BlackBishop => 3,
+            _ => 4,
+        },
+    };
+    m.a.index() as u64 | (m.b.index() as u64) << 6 | promotion << 12
+}
+
+fn unpack_move(data: u64) -> Option<Move> {
+    if data == 0 {
+        return None;
+    }
+    let a = Position::from_index((data & 0x3F) as usize);
+    let b = Position::from_index(((data >> 6) & 0x3F) as usize);
+    Some(match (data >> 12) & 0b111 {
+        0 => Move::new(a, b),
+        p => Move::with_promotion(a, b, Move::promoting_color(b).promotions()[p as usize - 1]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bound, Entry, TranspositionTable};
+    use crate::search::MATE;
+    use crate::{Move, Piece};
+
+    /// Spread over the table, it's indexed by the high bits
+    fn hash(n: u64) -> u64 {
+        n.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn entry(m: Option<&str>, score: i32, depth: u32, bound: Bound) -> Entry {
+        Entry { m: m.map(Move::from_str), score, depth, bound }
+    }
+
+    #[test]
+    fn round_trip() {
+        let tt = TranspositionTable::new(1);
+        tt.new_search();
+        assert!(tt.probe(hash(42), 0).is_none());
+        for (n, m, score, bound) in [(1, "e2e4", 35, Bound::Exact), (2, "a7a8n", -1234, Bound::Lower), (3, "b2b1r", 0, Bound::Upper)].iter() {
+            tt.store(hash(*n), 3, entry(Some(m), *score, 7, *bound));
+            let stored = tt.probe(hash(*n), 3).unwrap();
+            assert!(stored.m == Some(Move::from_str(m)), "{}", m);
+            assert_eq!((stored.score, stored.depth, stored.bound), (*score, 7, *bound));
+        }
+        // Underpromotions keep both the piece and its color
+        assert!(tt.probe(hash(2), 0).unwrap().m.unwrap().promotion == Some(Piece::WhiteKnight));
+        assert!(tt.probe(hash(3), 0).unwrap().m.unwrap().promotion == Some(Piece::BlackRook));
+        tt.store(hash(4), 0, entry(None, 0, 300, Bound::Exact));
+        let stored = tt.probe(hash(4), 0).unwrap();
+        assert!(stored.m.is_none());
+        assert_eq!(stored.depth, 255);
+    }
+
+    #[test]
+    fn mate_scores() {
+        let tt = TranspositionTable::new(1);
+        tt.new_search();
+        // Mate 10 plies from the root found 4 plies in is 6 plies from the position
+        tt.store(hash(1), 4, entry(None, MATE - 10, 5, Bound::Exact));
+        assert_eq!(tt.probe(hash(1), 4).unwrap().score, MATE - 10);
+        assert_eq!(tt.probe(hash(1), 2).unwrap().score, MATE - 8);
+        tt.store(hash(2), 4, entry(None, -(MATE - 10), 5, Bound::Exact));
+        assert_eq!(tt.probe(hash(2), 6).unwrap().score, -(MATE - 12));
+        // Other scores don't depend on the ply
+        tt.store(hash(3), 4, entry(None, -900, 5, Bound::Exact));
+        assert_eq!(tt.probe(hash(3), 9).unwrap().score, -900);
+    }
+
+    #[test]
+    fn replacement() {
+        // A single slot, every position lands in it
+        let tt = TranspositionTable::new(0);
+        tt.new_search();
+        tt.store(1, 0, entry(Some("e2e4"), 10, 8, Bound::Lower));
+        // A shallower entry of the same search about another position is dropped
+        tt.store(2, 0, entry(Some("d2d4"), 20, 3, Bound::Exact));
+        assert!(tt.probe(2, 0).is_none());
+        assert_eq!(tt.probe(1, 0).unwrap().depth, 8);
+        // The same position is always updated, keeping its move if the new entry has none
+        tt.store(1, 0, entry(None, 15, 2, Bound::Upper));
+        let stored = tt.probe(1, 0).unwrap();
+        assert!(stored.m == Some(Move::from_str("e2e4")));
+        assert_eq!((stored.score, stored.depth, stored.bound), (15, 2, Bound::Upper));
+        tt.store(2, 0, entry(Some("d2d4"), 20, 3, Bound::Exact));
+        assert!(tt.probe(1, 0).is_none());
+        // Deep entries of an older search make way
+        tt.store(1, 0, entry(None, 10, 20, Bound::Exact));
+        tt.new_search();
+        tt.store(2, 0, entry(None, 20, 1, Bound::Exact));
+        assert!(tt.probe(1, 0).is_none());
+        assert_eq!(tt.probe(2, 0).unwrap().depth, 1);
+    }
+}
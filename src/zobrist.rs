@@ -0,0 +1,61 @@
+//! Random keys for Zobrist hashing, a position's hash is the xor of the keys of
+//! everything in it, so a move only has to xor in and out what it changed
+
+pub struct Keys {
+    /// Indexed by `Piece as usize` and square
+    pub pieces: [[u64; 64]; 12],
+    pub black_to_move: u64,
+    /// White king side, white queen side, black king side, black queen side
+    pub castling: [u64; 4],
+    /// Indexed by the file of the en passant square
+    pub en_passant: [u64; 8],
+}
+
+pub static KEYS: Keys = Keys::new();
+
+/// SplitMix64, good enough to fill the tables at compile time
+const fn next(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+impl Keys {
+    const fn new() -> Keys {
+        let mut state = 0x7A4E_7A4E_7A4E_7A4E;
+
+        let mut pieces = [[0; 64]; 12];
+        let mut i = 0;
+        while i < 12 * 64 {
+            let (s, key) = next(state);
+            state = s;
+            pieces[i / 64][i % 64] = key;
+            i += 1;
+        }
+
+        let (s, black_to_move) = next(state);
+        state = s;
+
+        let mut castling = [0; 4];
+        let mut i = 0;
+        while i < 4 {
+            let (s, key) = next(state);
+            state = s;
+            castling[i] = key;
+            i += 1;
+        }
+
+        let mut en_passant = [0; 8];
+        let mut i = 0;
+        while i < 8 {
+            let (s, key) = next(state);
+            state = s;
+            en_passant[i] = key;
+            i += 1;
+        }
+
+        Keys { pieces, black_to_move, castling, en_passant }
+    }
+}